@@ -0,0 +1,294 @@
+// Copyright 2017 Matthias Tellen
+//
+// Permission is hereby granted,  free of charge,  to any person  obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without  limitation  the  rights to use,  copy,  modify,  merge,  publish,  distribute,
+// sublicense,  and/or sell copies of the Software,  and to permit  persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The  above  copyright  notice and this permission notice shall be included  in all copies or sub-
+// stantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS",  WITHOUT WARRANTY OF ANY KIND,  EXPRESS OR IMPLIED,  INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,  FITNESS  FOR A PARTICULAR PURPOSE AND NON-
+// INFRINGEMENT.  IN NO EVENT SHALL THE AUTHORS  OR  COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAM-
+// AGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,  TORT OR OTHERWISE, ARISING FROM, OUT
+// OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Angles that know their unit.
+//!
+//! `Radians` and `Degrees` wrap a finite `Clean`, so their trigonometric functions never return
+//! NaN. Results that would overflow, like the sum of two huge angles, are reduced by full turns
+//! first, which doesn't change the direction the angle points to.
+
+use core::ops::{Add, Neg, Sub};
+use num_traits::float::Float;
+use super::{AsRaw, Clean, UncheckedConv};
+use ::error::FloatError;
+use ::nanpack::NanPack;
+
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub struct Radians<F: Float>(Clean<F>);
+
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub struct Degrees<F: Float>(Clean<F>);
+
+#[inline]
+fn constant<F: Float>(f: f64) -> F {
+    F::from(f).unwrap_or_else(F::zero)
+}
+
+// `x` modulo `turn` in `[0, turn)`
+#[inline]
+fn reduce<F: Float>(x: F, turn: F) -> F {
+    let r = x % turn;
+    let r = if r < F::zero() { r + turn } else { r };
+    // adding a full turn to a tiny negative value can round up to the turn
+    if r < turn { r } else { F::zero() }
+}
+
+macro_rules! impl_angle {
+    ( $( $name: ident, $turn: expr; )* ) => {
+        $(
+            impl<F> $name<F>
+            where
+                F: Float + NanPack<usize>,
+            {
+                /// Fails if `value` is infinite.
+                #[inline]
+                #[track_caller]
+                pub fn new(value: Clean<F>) -> Result<Self, FloatError> {
+                    if value.as_raw().is_infinite() {
+                        return Err(FloatError::non_finite_angle(value.as_raw()));
+                    }
+                    Ok($name(value))
+                }
+
+                #[inline]
+                pub fn value(self) -> Clean<F> {
+                    self.0
+                }
+
+                #[inline]
+                fn turn() -> F {
+                    constant($turn)
+                }
+
+                /// The angle of the point `(x, y)`, in `[-π, π]` or `[-180, 180]`. The result is
+                /// always finite, also for infinite coordinates.
+                #[inline]
+                pub fn atan2(y: Clean<F>, x: Clean<F>) -> Self {
+                    Radians(y.map(|y| y.atan2(x.as_raw()))).into()
+                }
+
+                /// The same angle in `[0, 2π)` or `[0, 360)`.
+                #[inline]
+                pub fn normalize(self) -> Self {
+                    $name(self.0.map(|x| reduce(x, Self::turn())))
+                }
+
+                /// The same angle in `(-π, π]` or `(-180, 180]`.
+                #[inline]
+                pub fn normalize_signed(self) -> Self {
+                    let turn = Self::turn();
+                    let half = turn / (F::one() + F::one());
+                    $name(self.0.map(|x| {
+                        let r = reduce(x, turn);
+                        if r > half { r - turn } else { r }
+                    }))
+                }
+
+                // adds the raw values, reducing them first if the sum overflows
+                #[inline]
+                fn combine<C: Fn(F, F) -> F>(self, other: Self, c: C) -> Self {
+                    let (a, b) = (self.0.as_raw(), other.0.as_raw());
+                    let r = c(a, b);
+                    if r.is_finite() {
+                        return $name(Clean::from_raw(r));
+                    }
+                    let turn = Self::turn();
+                    $name(Clean::from_raw(c(reduce(a, turn), reduce(b, turn))))
+                }
+
+                #[inline]
+                pub fn sin(self) -> Clean<F> {
+                    Radians::from(self).0.map(Float::sin)
+                }
+
+                #[inline]
+                pub fn cos(self) -> Clean<F> {
+                    Radians::from(self).0.map(Float::cos)
+                }
+
+                #[inline]
+                pub fn tan(self) -> Clean<F> {
+                    Radians::from(self).0.map(Float::tan)
+                }
+
+                #[inline]
+                pub fn sin_cos(self) -> (Clean<F>, Clean<F>) {
+                    let (s, c) = Radians::from(self).0.as_raw().sin_cos();
+                    (Clean::from_raw(s), Clean::from_raw(c))
+                }
+            }
+
+            impl<F> Add for $name<F>
+            where
+                F: Float + NanPack<usize>,
+            {
+                type Output = Self;
+
+                #[inline]
+                fn add(self, other: Self) -> Self {
+                    self.combine(other, |a, b| a + b)
+                }
+            }
+
+            impl<F> Sub for $name<F>
+            where
+                F: Float + NanPack<usize>,
+            {
+                type Output = Self;
+
+                #[inline]
+                fn sub(self, other: Self) -> Self {
+                    self.combine(other, |a, b| a - b)
+                }
+            }
+
+            impl<F> Neg for $name<F>
+            where
+                F: Float + NanPack<usize>,
+            {
+                type Output = Self;
+
+                #[inline]
+                fn neg(self) -> Self {
+                    $name(self.0.map(Neg::neg))
+                }
+            }
+        )*
+    }
+}
+
+impl_angle! {
+    Radians, 2.0 * ::core::f64::consts::PI;
+    Degrees, 360.0;
+}
+
+impl<F> From<Degrees<F>> for Radians<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    fn from(d: Degrees<F>) -> Self {
+        Radians(d.0.map(Float::to_radians))
+    }
+}
+
+impl<F> From<Radians<F>> for Degrees<F>
+where
+    F: Float + NanPack<usize>,
+{
+    /// Reduces huge angles by full turns if converting them would overflow.
+    #[inline]
+    fn from(r: Radians<F>) -> Self {
+        let d = r.0.as_raw().to_degrees();
+        if d.is_finite() {
+            return Degrees(Clean::from_raw(d));
+        }
+        Degrees(r.normalize().0.map(Float::to_degrees))
+    }
+}
+
+impl<F> Radians<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    pub fn to_degrees(self) -> Degrees<F> {
+        self.into()
+    }
+}
+
+impl<F> Degrees<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    pub fn to_radians(self) -> Radians<F> {
+        self.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+    use F64;
+
+    fn rad(f: f64) -> Radians<f64> {
+        Radians::new(F64::try_new(f).unwrap()).unwrap()
+    }
+
+    fn deg(f: f64) -> Degrees<f64> {
+        Degrees::new(F64::try_new(f).unwrap()).unwrap()
+    }
+
+    fn close(a: Clean<f64>, b: f64) -> bool {
+        (a.as_raw() - b).abs() < 1e-12
+    }
+
+    #[test]
+    fn new() {
+        assert!(Radians::new(F64::try_new(::std::f64::INFINITY).unwrap()).is_err());
+        let err = Degrees::new(F64::try_new(-::std::f64::INFINITY).unwrap()).unwrap_err();
+        assert!(format!("{}", err).ends_with("Angle of negative infinity is not finite"));
+    }
+
+    #[test]
+    fn trigonometry() {
+        assert!(close(rad(PI / 2.0).sin(), 1.0));
+        assert!(close(deg(90.0).sin(), 1.0));
+        assert!(close(deg(180.0).cos(), -1.0));
+        assert!(close(deg(45.0).tan(), 1.0));
+        let (s, c) = deg(30.0).sin_cos();
+        assert!(close(s, 0.5) && close(c, 3f64.sqrt() / 2.0));
+        let one = F64::try_new(1.0).unwrap();
+        assert!(close(Degrees::atan2(one, one).value(), 45.0));
+        assert!(close(Radians::atan2(one, -one).value(), 3.0 * PI / 4.0));
+        let inf = F64::try_new(::std::f64::INFINITY).unwrap();
+        assert!(close(Degrees::atan2(inf, inf).value(), 45.0));
+    }
+
+    #[test]
+    fn conversions() {
+        assert!(close(deg(180.0).to_radians().value(), PI));
+        assert!(close(rad(PI).to_degrees().value(), 180.0));
+        let huge = Radians::new(F64::try_new(::std::f64::MAX).unwrap()).unwrap();
+        let d = huge.to_degrees().value();
+        assert!((0.0..360.0).contains(&d.as_raw()));
+    }
+
+    #[test]
+    fn normalization() {
+        assert!(close(deg(-90.0).normalize().value(), 270.0));
+        assert!(close(deg(720.0).normalize().value(), 0.0));
+        assert!(close(deg(270.0).normalize_signed().value(), -90.0));
+        assert!(close(deg(180.0).normalize_signed().value(), 180.0));
+        assert!(close(deg(-180.0).normalize_signed().value(), 180.0));
+        assert!(close(rad(3.0 * PI).normalize().value(), PI));
+        // rounds up to a full turn
+        assert_eq!(rad(-1e-20).normalize().value(), 0.0);
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert!(close((deg(350.0) + deg(20.0)).normalize().value(), 10.0));
+        assert!(close((deg(10.0) - deg(20.0)).value(), -10.0));
+        assert!(close((-deg(10.0)).value(), -10.0));
+        let max = deg(::std::f64::MAX);
+        let sum = (max + max).value();
+        assert!(sum.as_raw().is_finite());
+    }
+}
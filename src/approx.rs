@@ -0,0 +1,188 @@
+// Copyright 2017 Matthias Tellen
+//
+// Permission is hereby granted,  free of charge,  to any person  obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without  limitation  the  rights to use,  copy,  modify,  merge,  publish,  distribute,
+// sublicense,  and/or sell copies of the Software,  and to permit  persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The  above  copyright  notice and this permission notice shall be included  in all copies or sub-
+// stantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS",  WITHOUT WARRANTY OF ANY KIND,  EXPRESS OR IMPLIED,  INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,  FITNESS  FOR A PARTICULAR PURPOSE AND NON-
+// INFRINGEMENT.  IN NO EVENT SHALL THE AUTHORS  OR  COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAM-
+// AGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,  TORT OR OTHERWISE, ARISING FROM, OUT
+// OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Neighbouring floats and approximate comparison. Since a `Clean` is never NaN, the distance
+//! between two values in units in the last place is always well-defined.
+
+/// Bit-level stepping through the representable floats.
+pub trait Ulp: Sized {
+    /// The smallest float that is greater than `self`. Positive infinity and NaN are returned
+    /// unchanged.
+    fn next_up(self) -> Self;
+    /// The greatest float that is less than `self`. Negative infinity and NaN are returned
+    /// unchanged.
+    fn next_down(self) -> Self;
+    /// The number of steps with `next_up` that are needed to get from the smaller to the greater
+    /// value. Both zeros count as the same value. `None` if one of them is NaN.
+    fn ulps_between(self, other: Self) -> Option<u64>;
+}
+
+// just a little macro to avoid repeating the same stuff for f64 and f32
+macro_rules! impl_Ulp {
+    ( $f: ident, $u: ty, $i: ty ) => {
+        impl Ulp for $f {
+            fn next_up(self) -> Self {
+                let bits = self.to_bits();
+                if self.is_nan() || self == ::core::$f::INFINITY {
+                    return self;
+                }
+                let abs = bits & !(1 << (<$u>::max_value().count_ones() - 1));
+                let next = if abs == 0 {
+                    // the smallest positive subnormal, also for negative zero
+                    1
+                } else if bits == abs {
+                    bits + 1
+                } else {
+                    bits - 1
+                };
+                $f::from_bits(next)
+            }
+
+            fn next_down(self) -> Self {
+                -(-self).next_up()
+            }
+
+            fn ulps_between(self, other: Self) -> Option<u64> {
+                if self.is_nan() || other.is_nan() {
+                    return None;
+                }
+                // maps the floats onto integers with the same order, both zeros end up at 0
+                fn ordered(f: $f) -> i64 {
+                    let bits = f.to_bits() as $i;
+                    let ordered = if bits < 0 {
+                        <$i>::min_value().wrapping_sub(bits)
+                    } else {
+                        bits
+                    };
+                    ordered as i64
+                }
+                let (a, b) = (ordered(self) as i128, ordered(other) as i128);
+                Some((a - b).unsigned_abs() as u64)
+            }
+        }
+    }
+}
+
+impl_Ulp!(f64, u64, i64);
+impl_Ulp!(f32, u32, i32);
+
+/// Asserts that two floats are approximately equal, see `CleanFloat::approx_eq_ulps`,
+/// `CleanFloat::approx_eq_rel` and `CleanFloat::approx_eq_abs`. Without a tolerance, 4 ulps are
+/// allowed.
+///
+/// ```
+/// #[macro_use]
+/// extern crate clean_float;
+/// use clean_float::F64;
+///
+/// # fn main() {
+/// let third = (F64::try_new(1.0).unwrap() / 3.0).sanitize().unwrap();
+/// assert_clean_close!(third * 3.0, 1.0);
+/// assert_clean_close!(third, 0.333, abs = 0.001);
+/// assert_clean_close!(third, 0.3333, rel = 1e-4);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! assert_clean_close {
+    ($left: expr, $right: expr) => {
+        assert_clean_close!($left, $right, ulps = 4)
+    };
+    ($left: expr, $right: expr, ulps = $tol: expr) => {
+        assert_clean_close!(@check approx_eq_ulps, "ulps", $left, $right, $tol)
+    };
+    ($left: expr, $right: expr, rel = $tol: expr) => {
+        assert_clean_close!(@check approx_eq_rel, "rel", $left, $right, $tol)
+    };
+    ($left: expr, $right: expr, abs = $tol: expr) => {
+        assert_clean_close!(@check approx_eq_abs, "abs", $left, $right, $tol)
+    };
+    (@check $method: ident, $kind: expr, $left: expr, $right: expr, $tol: expr) => {{
+        let (left, right, tol) = ($left, $right, $tol);
+        if !$crate::CleanFloat::$method(left, right, tol) {
+            panic!(
+                concat!(
+                    "assertion failed: `(left ≈ right)`\n",
+                    "  left: `{:?}`,\n right: `{:?}`,\n {:>5}: `{:?}`"
+                ),
+                left, right, $kind, tol
+            );
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {CleanFloat, DirtyF64, F32, F64};
+
+    #[test]
+    fn next() {
+        use std::f64::{INFINITY as INF, MAX, MIN_POSITIVE};
+
+        assert_eq!(1f64.next_up(), 1.0 + ::std::f64::EPSILON);
+        assert_eq!(1f64.next_down(), 1.0 - ::std::f64::EPSILON / 2.0);
+        assert_eq!(MAX.next_up(), INF);
+        assert_eq!(INF.next_up(), INF);
+        assert_eq!((-INF).next_up(), -MAX);
+        assert_eq!((-INF).next_down(), -INF);
+        assert_eq!(0f64.next_up(), (-0f64).next_up());
+        assert!(0f64.next_up() > 0.0 && 0f64.next_up() < MIN_POSITIVE);
+        assert_eq!(0f64.next_up().next_down(), 0.0);
+        assert!(::std::f64::NAN.next_up().is_nan());
+        assert_eq!(1f32.next_up(), 1.0 + ::std::f32::EPSILON);
+    }
+
+    #[test]
+    fn ulps_between() {
+        assert_eq!(1f64.ulps_between(1f64.next_up().next_up()), Some(2));
+        assert_eq!(0f64.ulps_between(-0f64), Some(0));
+        assert_eq!(0f32.next_down().ulps_between(0f32.next_up()), Some(2));
+        assert_eq!(::std::f64::MAX.ulps_between(::std::f64::INFINITY), Some(1));
+        assert_eq!(::std::f64::NAN.ulps_between(1.0), None);
+        let inf = ::std::f64::INFINITY;
+        assert_eq!((-inf).ulps_between(inf), Some(0xFFE0_0000_0000_0000));
+    }
+
+    #[test]
+    fn clean_float() {
+        let one = F64::try_new(1.0).unwrap();
+        assert_eq!(one.next_up().next_down(), one);
+        assert_eq!(one.ulp(), ::std::f64::EPSILON);
+        assert_eq!((-one).ulp(), ::std::f64::EPSILON);
+        assert_eq!(F64::try_new(::std::f64::INFINITY).unwrap().ulp(), ::std::f64::INFINITY);
+
+        let tenth = (one / 10.0).sanitize().unwrap();
+        let sum = (0..10).fold(DirtyF64::new(0.0), |acc, _| acc + tenth);
+        assert!(sum != 1.0);
+        assert!(sum.approx_eq_ulps(one, 1));
+        assert!(sum.approx_eq_rel(one, 1e-15));
+        assert!(sum.approx_eq_abs(1.0, 1e-15));
+        assert!(!sum.approx_eq_abs(1.0, 1e-17));
+        assert!(!DirtyF64::new(::std::f64::NAN).approx_eq_ulps(1.0, 1000));
+
+        let inf = F32::try_new(::std::f32::INFINITY).unwrap();
+        assert!(inf.approx_eq_rel(inf, 0.1));
+        assert!(!inf.approx_eq_rel(::std::f32::MAX, 0.1));
+        assert!(!inf.approx_eq_abs(::std::f32::MAX, 1e30));
+    }
+
+    #[test]
+    #[should_panic]
+    fn assert_close() {
+        assert_clean_close!(F64::try_new(1.0).unwrap(), 1.1, rel = 0.01);
+    }
+}
@@ -0,0 +1,272 @@
+// Copyright 2017 Matthias Tellen
+//
+// Permission is hereby granted,  free of charge,  to any person  obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without  limitation  the  rights to use,  copy,  modify,  merge,  publish,  distribute,
+// sublicense,  and/or sell copies of the Software,  and to permit  persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The  above  copyright  notice and this permission notice shall be included  in all copies or sub-
+// stantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS",  WITHOUT WARRANTY OF ANY KIND,  EXPRESS OR IMPLIED,  INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,  FITNESS  FOR A PARTICULAR PURPOSE AND NON-
+// INFRINGEMENT.  IN NO EVENT SHALL THE AUTHORS  OR  COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAM-
+// AGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,  TORT OR OTHERWISE, ARISING FROM, OUT
+// OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Sanitization of many values at the end of a block, see `batch`.
+
+use core::fmt;
+use std::collections::BTreeSet;
+use failure::Fail;
+use num_traits::float::Float;
+use super::{AsRaw, Clean, Dirty};
+use ::error::FloatError;
+use ::nanpack::NanPack;
+
+/// Collects values that have to be clean at the end of a `batch`.
+pub struct Batch<F> {
+    registered: Vec<F>,
+}
+
+impl<F> Batch<F>
+where
+    F: Float + NanPack<usize>,
+{
+    /// Registers a value that has to be clean when the batch ends and passes it through. Only
+    /// needed for values that aren't returned from the batch.
+    #[inline]
+    pub fn check<D: AsRaw<F> + Copy>(&mut self, d: D) -> D {
+        self.registered.push(d.as_raw());
+        d
+    }
+}
+
+/// Sanitizes the values of a batch and collects the errors. A NaN that was passed on from one
+/// value to another is reported once.
+pub struct BatchSanitizer<F> {
+    errors: Vec<FloatError>,
+    seen: BTreeSet<usize>,
+    _float: ::core::marker::PhantomData<F>,
+}
+
+impl<F> BatchSanitizer<F>
+where
+    F: Float + NanPack<usize>,
+{
+    pub fn sanitize(&mut self, f: F) -> Option<Clean<F>> {
+        if let Some(payload) = f.get_payload() {
+            if !self.seen.insert(payload) {
+                return None;
+            }
+        }
+        match Clean::try_new(f) {
+            Ok(c) => Some(c),
+            Err(err) => {
+                self.errors.push(err);
+                None
+            }
+        }
+    }
+}
+
+/// Values that can be returned from a `batch`: `Dirty` and `Clean` values, tuples and vectors
+/// of them.
+pub trait SanitizeAll<F> {
+    type Clean;
+
+    /// Sanitizes every value, without stopping at the first error.
+    fn sanitize_all(self, s: &mut BatchSanitizer<F>) -> Option<Self::Clean>;
+}
+
+impl<F> SanitizeAll<F> for Dirty<F>
+where
+    F: Float + NanPack<usize>,
+{
+    type Clean = Clean<F>;
+
+    #[inline]
+    fn sanitize_all(self, s: &mut BatchSanitizer<F>) -> Option<Clean<F>> {
+        s.sanitize(self.as_raw())
+    }
+}
+
+impl<F> SanitizeAll<F> for Clean<F>
+where
+    F: Float + NanPack<usize>,
+{
+    type Clean = Clean<F>;
+
+    #[inline]
+    fn sanitize_all(self, _: &mut BatchSanitizer<F>) -> Option<Clean<F>> {
+        Some(self)
+    }
+}
+
+impl<F, T> SanitizeAll<F> for Vec<T>
+where
+    T: SanitizeAll<F>,
+{
+    type Clean = Vec<T::Clean>;
+
+    fn sanitize_all(self, s: &mut BatchSanitizer<F>) -> Option<Self::Clean> {
+        let cleaned: Vec<_> = self.into_iter().map(|t| t.sanitize_all(s)).collect();
+        cleaned.into_iter().collect()
+    }
+}
+
+impl<F> SanitizeAll<F> for () {
+    type Clean = ();
+
+    #[inline]
+    fn sanitize_all(self, _: &mut BatchSanitizer<F>) -> Option<()> {
+        Some(())
+    }
+}
+
+macro_rules! impl_sanitize_all_tuple {
+    ( $( ( $( $t: ident $v: ident ),* ) )* ) => {
+        $(
+            impl<F, $( $t ),*> SanitizeAll<F> for ( $( $t, )* )
+            where
+                $( $t: SanitizeAll<F> ),*
+            {
+                type Clean = ( $( $t::Clean, )* );
+
+                #[inline]
+                fn sanitize_all(self, s: &mut BatchSanitizer<F>) -> Option<Self::Clean> {
+                    let ( $( $v, )* ) = self;
+                    // every value is sanitized before the first failure is returned
+                    $( let $v = $v.sanitize_all(s); )*
+                    Some(( $( $v?, )* ))
+                }
+            }
+        )*
+    }
+}
+
+impl_sanitize_all_tuple! {
+    (A a)
+    (A a, B b)
+    (A a, B b, C c)
+    (A a, B b, C c, D d)
+    (A a, B b, C c, D d, E e)
+    (A a, B b, C c, D d, E e, G g)
+    (A a, B b, C c, D d, E e, G g, H h)
+    (A a, B b, C c, D d, E e, G g, H h, I i)
+}
+
+/// The errors of all values of a batch that were NaN.
+pub struct BatchFloatError {
+    errors: Vec<FloatError>,
+}
+
+impl BatchFloatError {
+    pub fn errors(&self) -> &[FloatError] {
+        &self.errors
+    }
+
+    pub fn into_errors(self) -> Vec<FloatError> {
+        self.errors
+    }
+}
+
+impl fmt::Display for BatchFloatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} NaN(s) in batch", self.errors.len())?;
+        for err in &self.errors {
+            write!(f, "\n  {}", err)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for BatchFloatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Fail for BatchFloatError {}
+
+/// Runs `c` and sanitizes everything it returns, along with the values it registered with
+/// `Batch::check`. Either all of them are clean, or the errors of all NaNs are returned.
+///
+/// ```
+/// # extern crate clean_float;
+/// # use clean_float::{batch, F64};
+/// # fn main() {
+/// let (a, b) = (F64::try_new(1.0).unwrap(), F64::try_new(0.0).unwrap());
+/// let result = batch(|s| {
+///     let ratio = s.check(a / b);
+///     (ratio * b, a + b)
+/// });
+/// assert_eq!(result.unwrap_err().errors().len(), 1);
+/// # }
+/// ```
+pub fn batch<F, T, C>(c: C) -> Result<T::Clean, BatchFloatError>
+where
+    F: Float + NanPack<usize>,
+    T: SanitizeAll<F>,
+    C: FnOnce(&mut Batch<F>) -> T,
+{
+    let mut b = Batch { registered: Vec::new() };
+    let result = c(&mut b);
+    let mut s = BatchSanitizer {
+        errors: Vec::new(),
+        seen: BTreeSet::new(),
+        _float: ::core::marker::PhantomData,
+    };
+    // the returned values first, they are what the caller is interested in
+    let clean = result.sanitize_all(&mut s);
+    for f in b.registered {
+        s.sanitize(f);
+    }
+    match clean {
+        Some(clean) if s.errors.is_empty() => Ok(clean),
+        _ => Err(BatchFloatError { errors: s.errors }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use F64;
+
+    #[test]
+    fn clean() {
+        let (a, b) = (F64::try_new(3.0).unwrap(), F64::try_new(4.0).unwrap());
+        let (sum, product, same) = batch(|_| (a + b, a * b, a)).unwrap();
+        assert_eq!(sum, 7.0);
+        assert_eq!(product, 12.0);
+        assert_eq!(same, 3.0);
+        let all = batch(|_| vec![a / b, b / a]).unwrap();
+        assert_eq!(all.len(), 2);
+        assert!(batch::<f64, _, _>(|_| ()).is_ok());
+    }
+
+    #[test]
+    fn all_errors() {
+        let zero = F64::try_new(0.0).unwrap();
+        let inf = F64::try_new(::std::f64::INFINITY).unwrap();
+        let err = batch(|s| {
+            s.check(zero / zero);
+            (zero * inf, inf - inf, zero)
+        }).unwrap_err();
+        assert_eq!(err.errors().len(), 3);
+        assert!(format!("{}", err).starts_with("3 NaN(s) in batch"));
+    }
+
+    #[test]
+    #[cfg(not(build = "release"))]
+    fn passed_on_once() {
+        let zero = F64::try_new(0.0).unwrap();
+        let err = batch(|s| {
+            let nan = s.check(zero / zero);
+            (nan * zero, nan + zero)
+        }).unwrap_err();
+        assert_eq!(err.errors().len(), 1);
+        assert!(format!("{}", err.errors()[0]).ends_with("Division zero by zero resulted in NaN"));
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright 2017 Matthias Tellen
+//
+// Permission is hereby granted,  free of charge,  to any person  obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without  limitation  the  rights to use,  copy,  modify,  merge,  publish,  distribute,
+// sublicense,  and/or sell copies of the Software,  and to permit  persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The  above  copyright  notice and this permission notice shall be included  in all copies or sub-
+// stantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS",  WITHOUT WARRANTY OF ANY KIND,  EXPRESS OR IMPLIED,  INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,  FITNESS  FOR A PARTICULAR PURPOSE AND NON-
+// INFRINGEMENT.  IN NO EVENT SHALL THE AUTHORS  OR  COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAM-
+// AGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,  TORT OR OTHERWISE, ARISING FROM, OUT
+// OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Arithmetic on `Clean` that fails right away instead of returning a `Dirty`.
+//!
+//! The `checked_*` methods fail if the result would be NaN, the `saturating_*` methods
+//! additionally clamp results that overflow to infinity to `±MAX`. Infinite operands are valid
+//! `Clean` values and their infinite results pass through unchanged. Since the error is returned
+//! directly, it never passes through the error buffer.
+
+use num_traits::float::Float;
+use super::{AsRaw, Clean, UncheckedConv};
+use ::error::FloatError;
+use ::nanpack::NanPack;
+
+// clamps `f` if it's infinite although all the operands are finite
+#[inline]
+fn saturate<F: Float>(operands: &[F], f: F) -> F {
+    if f.is_infinite() && operands.iter().all(|x| x.is_finite()) {
+        F::max_value().copysign(f)
+    } else {
+        f
+    }
+}
+
+macro_rules! checked_binary {
+    (
+        $( $checked: ident, $saturating: ident, $error: ident, |$a: ident, $b: ident| $op: expr; )*
+    ) => {
+        $(
+            #[inline]
+            #[track_caller]
+            pub fn $checked(self, other: Clean<F>) -> Result<Clean<F>, FloatError> {
+                let ($a, $b) = (self.as_raw(), other.as_raw());
+                let result = $op;
+                if result.is_nan() {
+                    return Err(FloatError::$error($a, $b));
+                }
+                Ok(Clean::from_raw(result))
+            }
+
+            #[inline]
+            #[track_caller]
+            pub fn $saturating(self, other: Clean<F>) -> Result<Clean<F>, FloatError> {
+                let operands = [self.as_raw(), other.as_raw()];
+                self.$checked(other).map(|c| c.map(|f| saturate(&operands, f)))
+            }
+        )*
+    }
+}
+
+macro_rules! checked_unary {
+    ( $( $checked: ident, $method: ident, $name: expr; )* ) => {
+        $(
+            #[inline]
+            #[track_caller]
+            pub fn $checked(self) -> Result<Clean<F>, FloatError> {
+                let a = self.as_raw();
+                let result = a.$method();
+                if result.is_nan() {
+                    return Err(FloatError::function($name, a));
+                }
+                Ok(Clean::from_raw(result))
+            }
+        )*
+    }
+}
+
+impl<F> Clean<F>
+where
+    F: Float + NanPack<usize>,
+{
+    checked_binary! {
+        checked_add, saturating_add, add, |a, b| a + b;
+        checked_sub, saturating_sub, sub, |a, b| a - b;
+        checked_mul, saturating_mul, mul, |a, b| a * b;
+        checked_div, saturating_div, div, |a, b| a / b;
+        checked_rem, saturating_rem, rem, |a, b| a % b;
+        checked_powf, saturating_powf, pow, |a, b| a.powf(b);
+    }
+
+    checked_unary! {
+        checked_sqrt, sqrt, "Square root";
+        checked_ln, ln, "Logarithm";
+        checked_log2, log2, "Logarithm";
+        checked_log10, log10, "Logarithm";
+        checked_ln_1p, ln_1p, "Logarithm";
+        checked_asin, asin, "Arcsine";
+        checked_acos, acos, "Arccosine";
+        checked_acosh, acosh, "Area hyperbolic cosine";
+        checked_atanh, atanh, "Area hyperbolic tangent";
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn checked_log(self, base: Clean<F>) -> Result<Clean<F>, FloatError> {
+        let (a, b) = (self.as_raw(), base.as_raw());
+        let result = a.log(b);
+        if result.is_nan() {
+            return Err(FloatError::function("Logarithm", a));
+        }
+        Ok(Clean::from_raw(result))
+    }
+
+    /// Integer powers of a non-NaN value are never NaN, only the saturating variant is needed.
+    #[inline]
+    pub fn saturating_powi(self, n: i32) -> Clean<F> {
+        self.map(|a| saturate(&[a], a.powi(n)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use F64;
+
+    fn c(f: f64) -> F64 {
+        F64::try_new(f).unwrap()
+    }
+
+    #[test]
+    fn checked() {
+        use std::f64::{INFINITY, MAX};
+
+        assert_eq!(c(1.0).checked_add(c(2.0)).unwrap(), 3.0);
+        assert!(c(INFINITY).checked_sub(c(INFINITY)).is_err());
+        assert!(c(INFINITY).checked_mul(c(0.0)).is_err());
+        assert_eq!(c(1.0).checked_div(c(0.0)).unwrap(), INFINITY);
+        assert!(c(0.0).checked_div(c(0.0)).is_err());
+        assert!(c(1.0).checked_rem(c(0.0)).is_err());
+        assert!(c(-8.0).checked_powf(c(0.5)).is_err());
+        assert!(c(-1.0).checked_sqrt().is_err());
+        assert!(c(2.0).checked_acos().is_err());
+        assert_eq!(c(8.0).checked_log(c(2.0)).unwrap(), 3.0);
+        assert_eq!(c(MAX).checked_add(c(MAX)).unwrap(), INFINITY);
+
+        let err = c(INFINITY).checked_sub(c(INFINITY)).unwrap_err();
+        assert!(format!("{}", err).ends_with(
+            "Subtraction of infinity minus infinity resulted in NaN"
+        ));
+        let err = c(-1.0).checked_ln().unwrap_err();
+        assert!(format!("{}", err).ends_with("Logarithm of value resulted in NaN"));
+    }
+
+    #[test]
+    fn saturating() {
+        use std::f64::{INFINITY, MAX};
+
+        assert_eq!(c(MAX).saturating_add(c(MAX)).unwrap(), MAX);
+        assert_eq!(c(-MAX).saturating_sub(c(MAX)).unwrap(), -MAX);
+        assert_eq!(c(MAX).saturating_mul(c(-2.0)).unwrap(), -MAX);
+        assert_eq!(c(1.0).saturating_div(c(-0.0)).unwrap(), -MAX);
+        assert_eq!(c(10.0).saturating_powf(c(400.0)).unwrap(), MAX);
+        assert_eq!(c(10.0).saturating_powi(400), MAX);
+        assert_eq!(c(INFINITY).saturating_add(c(1.0)).unwrap(), INFINITY);
+        assert_eq!(c(-INFINITY).saturating_mul(c(2.0)).unwrap(), -INFINITY);
+        assert_eq!(c(INFINITY).saturating_powi(2), INFINITY);
+        assert!(c(0.0).saturating_div(c(0.0)).is_err());
+    }
+
+    #[test]
+    fn pipeline() {
+        fn hypot(a: F64, b: F64) -> Result<F64, FloatError> {
+            a.checked_mul(a)?.checked_add(b.checked_mul(b)?)?.checked_sqrt()
+        }
+
+        assert_eq!(hypot(c(3.0), c(4.0)).unwrap(), 5.0);
+    }
+}
@@ -0,0 +1,486 @@
+// Copyright 2017 Matthias Tellen
+//
+// Permission is hereby granted,  free of charge,  to any person  obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without  limitation  the  rights to use,  copy,  modify,  merge,  publish,  distribute,
+// sublicense,  and/or sell copies of the Software,  and to permit  persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The  above  copyright  notice and this permission notice shall be included  in all copies or sub-
+// stantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS",  WITHOUT WARRANTY OF ANY KIND,  EXPRESS OR IMPLIED,  INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,  FITNESS  FOR A PARTICULAR PURPOSE AND NON-
+// INFRINGEMENT.  IN NO EVENT SHALL THE AUTHORS  OR  COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAM-
+// AGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,  TORT OR OTHERWISE, ARISING FROM, OUT
+// OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Complex numbers with `Clean` and `Dirty` parts.
+//!
+//! Like their real counterparts, operations on a `CleanComplex` return a `DirtyComplex`, which
+//! has to be sanitized. The arithmetic operators and the elementary functions record the error
+//! when they produce a NaN, so sanitizing reports where it came from.
+
+use core::ops::{Add, Div, Mul, Neg, Sub};
+use num_traits::float::Float;
+use super::{AsRaw, Clean, CleanFloat, Dirty, UncheckedConv};
+use ::error::FloatError;
+#[cfg(all(feature = "alloc", not(build = "release")))]
+use ::error::{FloatClass, FLOAT_ERROR_BUFFER};
+#[cfg(all(feature = "alloc", not(build = "release")))]
+use ::trait_impls::keep_first_error;
+use ::nanpack::NanPack;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CleanComplex<F: Float> {
+    pub re: Clean<F>,
+    pub im: Clean<F>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DirtyComplex<F: Float> {
+    pub re: Dirty<F>,
+    pub im: Dirty<F>,
+}
+
+/// Access to the raw parts of `CleanComplex` and `DirtyComplex`, so that both can be mixed in
+/// arithmetic.
+pub trait ComplexParts<F> {
+    fn parts(self) -> (F, F);
+}
+
+impl<F: Float> ComplexParts<F> for CleanComplex<F> {
+    #[inline]
+    fn parts(self) -> (F, F) {
+        (self.re.as_raw(), self.im.as_raw())
+    }
+}
+
+impl<F: Float> ComplexParts<F> for DirtyComplex<F> {
+    #[inline]
+    fn parts(self) -> (F, F) {
+        (self.re.as_raw(), self.im.as_raw())
+    }
+}
+
+// complex infinity has no meaningful sign, so the class only distinguishes the magnitude
+#[cfg(all(feature = "alloc", not(build = "release")))]
+fn class<F: Float>((re, im): (F, F)) -> FloatClass {
+    if re.is_nan() || im.is_nan() {
+        F::nan().into()
+    } else if re.is_infinite() || im.is_infinite() {
+        F::infinity().into()
+    } else if re.is_zero() && im.is_zero() {
+        F::zero().into()
+    } else {
+        F::one().into()
+    }
+}
+
+// the error of `op` on `z`, or on `z` and `w` for binary operations
+#[cfg(all(feature = "alloc", not(build = "release")))]
+#[track_caller]
+fn error<F: Float>(op: &'static str, z: (F, F), w: Option<(F, F)>) -> FloatError {
+    match w {
+        Some(w) => FloatError::complex_binary(op, class(z), class(w)),
+        None => FloatError::complex_unary(op, class(z)),
+    }
+}
+
+// Builds the result of `op` on `inputs`. An error stored in one of the inputs is passed on, a new
+// NaN is recorded with the error of `op` on the operands `z` and `w`. Only one part carries the
+// error, so that sanitizing the other part doesn't find it expired, and the errors of the other
+// inputs are removed from the buffer.
+#[cfg_attr(not(all(feature = "alloc", not(build = "release"))), allow(unused_variables))]
+#[inline]
+#[track_caller]
+fn finish<F>(
+    inputs: &[F],
+    (re, im): (F, F),
+    op: &'static str,
+    z: (F, F),
+    w: Option<(F, F)>,
+) -> DirtyComplex<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[cfg(all(feature = "alloc", not(build = "release")))]
+    {
+        let mut payloaded = inputs.iter().filter(|x| x.is_payloaded());
+        if let Some(&nan) = payloaded.next() {
+            let nan = payloaded.fold(nan, |kept, &other| keep_first_error(kept, other));
+            return DirtyComplex::from_parts(nan, F::nan());
+        }
+        if re.is_nan() {
+            let nan: F = FLOAT_ERROR_BUFFER.insert(error(op, z, w));
+            let im = if im.is_nan() { F::nan() } else { im };
+            return DirtyComplex::from_parts(nan, im);
+        }
+        if im.is_nan() {
+            return DirtyComplex::from_parts(re, FLOAT_ERROR_BUFFER.insert(error(op, z, w)));
+        }
+    }
+    DirtyComplex::from_parts(re, im)
+}
+
+fn mul<F: Float>((a, b): (F, F), (c, d): (F, F)) -> (F, F) {
+    (a * c - b * d, a * d + b * c)
+}
+
+// Smith's algorithm, which avoids overflow in the intermediate results
+fn div<F: Float>((a, b): (F, F), (c, d): (F, F)) -> (F, F) {
+    if c.abs() >= d.abs() {
+        let r = d / c;
+        let den = c + d * r;
+        ((a + b * r) / den, (b - a * r) / den)
+    } else {
+        let r = c / d;
+        let den = c * r + d;
+        ((a * r + b) / den, (b * r - a) / den)
+    }
+}
+
+fn exp<F: Float>((a, b): (F, F)) -> (F, F) {
+    let m = a.exp();
+    if b.is_zero() {
+        // keeps exp of a real infinity free of `inf * 0`
+        return (m, b);
+    }
+    let (s, c) = b.sin_cos();
+    (m * c, m * s)
+}
+
+fn ln<F: Float>((a, b): (F, F)) -> (F, F) {
+    (a.hypot(b).ln(), b.atan2(a))
+}
+
+fn sqrt<F: Float>((a, b): (F, F)) -> (F, F) {
+    if a.is_zero() && b.is_zero() {
+        return (F::zero(), b);
+    }
+    if b.is_infinite() {
+        return (F::infinity(), b);
+    }
+    // halving first keeps the sum from overflowing
+    let half = F::one() / (F::one() + F::one());
+    let t = (a.abs() * half + a.hypot(b) * half).sqrt();
+    if a >= F::zero() {
+        (t, b / (t + t))
+    } else {
+        (b.abs() / (t + t), t.copysign(b))
+    }
+}
+
+fn powc<F: Float>(z: (F, F), w: (F, F)) -> (F, F) {
+    let (zero, one) = (F::zero(), F::one());
+    if z.0.is_zero() && z.1.is_zero() {
+        if w.0.is_zero() && w.1.is_zero() {
+            return (one, zero);
+        }
+        if w.0 > zero {
+            return (zero, zero);
+        }
+    }
+    exp(mul(w, ln(z)))
+}
+
+impl<F> CleanComplex<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    pub fn new(re: Clean<F>, im: Clean<F>) -> Self {
+        CleanComplex { re, im }
+    }
+
+    #[inline]
+    fn from_parts(re: F, im: F) -> Self {
+        CleanComplex::new(Clean::from_raw(re), Clean::from_raw(im))
+    }
+
+    #[inline]
+    pub fn i() -> Self {
+        CleanComplex::from_parts(F::zero(), F::one())
+    }
+
+    #[inline]
+    pub fn conj(self) -> Self {
+        CleanComplex::new(self.re, Clean::from_raw(-self.im.as_raw()))
+    }
+
+    #[inline]
+    pub fn taint(self) -> DirtyComplex<F> {
+        DirtyComplex::new(self.re.taint(), self.im.taint())
+    }
+
+    /// The magnitude, which is infinite if either part is.
+    #[inline]
+    pub fn abs(self) -> Clean<F> {
+        self.re.hypot(self.im)
+    }
+
+    /// The argument in `[-π, π]`.
+    #[inline]
+    pub fn arg(self) -> Clean<F> {
+        self.im.atan2(self.re)
+    }
+
+    #[inline]
+    pub fn norm_sqr(self) -> Dirty<F> {
+        self.re * self.re + self.im * self.im
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn exp(self) -> DirtyComplex<F> {
+        let z = self.parts();
+        finish(&[], exp(z), "exponential", z, None)
+    }
+
+    /// The principal logarithm.
+    #[inline]
+    #[track_caller]
+    pub fn ln(self) -> DirtyComplex<F> {
+        let z = self.parts();
+        finish(&[], ln(z), "logarithm", z, None)
+    }
+
+    /// The principal square root, whose real part is non-negative.
+    #[inline]
+    #[track_caller]
+    pub fn sqrt(self) -> DirtyComplex<F> {
+        let z = self.parts();
+        finish(&[], sqrt(z), "square root", z, None)
+    }
+
+    /// Raises `self` to a complex power, using the principal logarithm.
+    #[inline]
+    #[track_caller]
+    pub fn powc<B: ComplexParts<F>>(self, w: B) -> DirtyComplex<F> {
+        let (z, w) = (self.parts(), w.parts());
+        finish(&[w.0, w.1], powc(z, w), "power", z, Some(w))
+    }
+}
+
+impl<F> DirtyComplex<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    pub fn new(re: Dirty<F>, im: Dirty<F>) -> Self {
+        DirtyComplex { re, im }
+    }
+
+    #[inline]
+    fn from_parts(re: F, im: F) -> Self {
+        DirtyComplex::new(Dirty::from_raw(re), Dirty::from_raw(im))
+    }
+
+    /// Fails with the error of the first part that is NaN.
+    #[inline]
+    pub fn sanitize(self) -> Result<CleanComplex<F>, FloatError> {
+        Ok(CleanComplex::new(self.re.sanitize()?, self.im.sanitize()?))
+    }
+}
+
+impl<F> From<Clean<F>> for CleanComplex<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    fn from(re: Clean<F>) -> Self {
+        CleanComplex::new(re, Clean::from_raw(F::zero()))
+    }
+}
+
+impl<F> From<CleanComplex<F>> for DirtyComplex<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    fn from(z: CleanComplex<F>) -> Self {
+        z.taint()
+    }
+}
+
+macro_rules! impl_complex_ops {
+    ( $( $name: ty ),* ) => {
+        $(
+            impl<B, F> Add<B> for $name
+            where
+                F: Float + NanPack<usize>,
+                B: ComplexParts<F>,
+            {
+                type Output = DirtyComplex<F>;
+
+                #[inline]
+                #[track_caller]
+                fn add(self, other: B) -> Self::Output {
+                    let (z, w) = (self.parts(), other.parts());
+                    let sum = (z.0 + w.0, z.1 + w.1);
+                    finish(&[z.0, z.1, w.0, w.1], sum, "addition", z, Some(w))
+                }
+            }
+
+            impl<B, F> Sub<B> for $name
+            where
+                F: Float + NanPack<usize>,
+                B: ComplexParts<F>,
+            {
+                type Output = DirtyComplex<F>;
+
+                #[inline]
+                #[track_caller]
+                fn sub(self, other: B) -> Self::Output {
+                    let (z, w) = (self.parts(), other.parts());
+                    let difference = (z.0 - w.0, z.1 - w.1);
+                    finish(&[z.0, z.1, w.0, w.1], difference, "subtraction", z, Some(w))
+                }
+            }
+
+            impl<B, F> Mul<B> for $name
+            where
+                F: Float + NanPack<usize>,
+                B: ComplexParts<F>,
+            {
+                type Output = DirtyComplex<F>;
+
+                #[inline]
+                #[track_caller]
+                fn mul(self, other: B) -> Self::Output {
+                    let (z, w) = (self.parts(), other.parts());
+                    finish(&[z.0, z.1, w.0, w.1], mul(z, w), "multiplication", z, Some(w))
+                }
+            }
+
+            impl<B, F> Div<B> for $name
+            where
+                F: Float + NanPack<usize>,
+                B: ComplexParts<F>,
+            {
+                type Output = DirtyComplex<F>;
+
+                #[inline]
+                #[track_caller]
+                fn div(self, other: B) -> Self::Output {
+                    let (z, w) = (self.parts(), other.parts());
+                    finish(&[z.0, z.1, w.0, w.1], div(z, w), "division", z, Some(w))
+                }
+            }
+
+            impl<F> Neg for $name
+            where
+                F: Float + NanPack<usize>,
+            {
+                type Output = Self;
+
+                #[inline]
+                fn neg(self) -> Self {
+                    let (a, b) = self.parts();
+                    Self::from_parts(-a, -b)
+                }
+            }
+        )*
+    }
+}
+
+impl_complex_ops!(CleanComplex<F>, DirtyComplex<F>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use F64;
+
+    fn c(re: f64, im: f64) -> CleanComplex<f64> {
+        CleanComplex::new(F64::try_new(re).unwrap(), F64::try_new(im).unwrap())
+    }
+
+    fn close(z: DirtyComplex<f64>, re: f64, im: f64) -> bool {
+        let z = z.sanitize().unwrap();
+        (z.re.as_raw() - re).abs() < 1e-12 && (z.im.as_raw() - im).abs() < 1e-12
+    }
+
+    #[test]
+    fn arithmetic() {
+        let (a, b) = (c(1.0, 2.0), c(3.0, -1.0));
+        assert!(close(a + b, 4.0, 1.0));
+        assert!(close(a - b, -2.0, 3.0));
+        assert!(close(a * b, 5.0, 5.0));
+        assert!(close(a / b, 0.1, 0.7));
+        assert!(close((a * b) / b, 1.0, 2.0));
+        assert_eq!((-a).re, -1.0);
+        assert_eq!(a.conj().im, -2.0);
+        assert!(close(CleanComplex::i() * CleanComplex::i(), -1.0, 0.0));
+    }
+
+    #[test]
+    fn functions() {
+        use std::f64::consts::{FRAC_PI_2, PI};
+
+        let z = c(3.0, 4.0);
+        assert_eq!(z.abs(), 5.0);
+        assert_eq!(c(-1.0, 0.0).arg(), PI);
+        assert!(close(c(0.0, PI).exp(), -1.0, 0.0));
+        assert!(close(c(-1.0, 0.0).ln(), 0.0, PI));
+        assert!(close(c(-4.0, 0.0).sqrt(), 0.0, 2.0));
+        assert!(close(c(3.0, 4.0).sqrt(), 2.0, 1.0));
+        assert!(close(CleanComplex::i().powc(CleanComplex::i()), (-FRAC_PI_2).exp(), 0.0));
+        assert!(close(c(0.0, 0.0).powc(c(2.0, 0.0)), 0.0, 0.0));
+        assert!(close(c(0.0, 0.0).powc(c(0.0, 0.0)), 1.0, 0.0));
+
+        let inf = ::std::f64::INFINITY;
+        let e = c(inf, 0.0).exp().sanitize().unwrap();
+        assert_eq!((e.re.as_raw(), e.im.as_raw()), (inf, 0.0));
+        let s = c(inf, 1.0).sqrt().sanitize().unwrap();
+        assert_eq!((s.re.as_raw(), s.im.as_raw()), (inf, 0.0));
+        let s = c(1.0, -inf).sqrt().sanitize().unwrap();
+        assert_eq!((s.re.as_raw(), s.im.as_raw()), (inf, -inf));
+    }
+
+    #[test]
+    #[cfg(all(feature = "alloc", not(build = "release")))]
+    fn nan_errors() {
+        let inf = ::std::f64::INFINITY;
+        let err = (c(inf, 0.0) * c(0.0, 0.0)).sanitize().unwrap_err();
+        assert_eq!(
+            format!("{}", err).split(": ").last(),
+            Some("Complex multiplication of infinity and zero resulted in NaN")
+        );
+        assert!((c(1.0, 1.0) / c(0.0, 0.0)).sanitize().is_err());
+        let err = (c(inf, 0.0) + c(-inf, 0.0)).sanitize().unwrap_err();
+        assert!(format!("{}", err).ends_with("addition of infinity and infinity resulted in NaN"));
+        let err = format!("{}", (c(0.0, inf) - c(0.0, inf)).sanitize().unwrap_err());
+        assert!(err.ends_with("subtraction of infinity and infinity resulted in NaN"));
+        assert!(c(inf, inf).exp().sanitize().is_err());
+        #[cfg(feature = "std")]
+        {
+            let (z, line) = (c(1.0, 1.0) / c(0.0, 0.0), line!());
+            let err = z.sanitize().unwrap_err();
+            assert!(format!("{}", err).starts_with(&format!("{}:{}:", file!(), line)));
+        }
+
+        // the error of a dirty operand is passed on
+        let dirty = c(inf, 0.0) * c(0.0, 0.0);
+        let err = (dirty * c(1.0, 0.0)).sanitize().unwrap_err();
+        assert!(format!("{}", err).ends_with("of infinity and zero resulted in NaN"));
+
+        // the other part is a plain NaN, and the error of the other operand is dropped
+        let (z, w) = (c(inf, 0.0) * c(0.0, 0.0), c(0.0, 0.0).ln() * c(0.0, 0.0));
+        let copy = w;
+        let product = z * w;
+        let err = product.im.sanitize().unwrap_err();
+        assert!(format!("{}", err).ends_with("Sanitization of NaN"), "{}", err);
+        let err = product.re.sanitize().unwrap_err();
+        assert!(format!("{}", err).ends_with("of infinity and zero resulted in NaN"));
+        let err = copy.sanitize().unwrap_err();
+        assert!(format!("{}", err).ends_with("already retrieved or overwritten"));
+
+        // the same holds for sums
+        let (z, w) = (c(inf, 0.0) * c(0.0, 0.0), c(0.0, 0.0).ln() * c(0.0, 0.0));
+        let copy = w;
+        let err = (z + w).re.sanitize().unwrap_err();
+        assert!(format!("{}", err).ends_with("of infinity and zero resulted in NaN"));
+        let err = copy.sanitize().unwrap_err();
+        assert!(format!("{}", err).ends_with("already retrieved or overwritten"));
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright 2017 Matthias Tellen
+//
+// Permission is hereby granted,  free of charge,  to any person  obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without  limitation  the  rights to use,  copy,  modify,  merge,  publish,  distribute,
+// sublicense,  and/or sell copies of the Software,  and to permit  persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The  above  copyright  notice and this permission notice shall be included  in all copies or sub-
+// stantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS",  WITHOUT WARRANTY OF ANY KIND,  EXPRESS OR IMPLIED,  INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,  FITNESS  FOR A PARTICULAR PURPOSE AND NON-
+// INFRINGEMENT.  IN NO EVENT SHALL THE AUTHORS  OR  COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAM-
+// AGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,  TORT OR OTHERWISE, ARISING FROM, OUT
+// OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use num_traits::float::Float;
+use super::{AsRaw, Clean};
+use ::error::FloatError;
+use ::nanpack::NanPack;
+
+/// How a float is rounded before it is converted to an integer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Rounding {
+    /// Fails if the value has a fractional part.
+    Exact,
+    /// Rounds towards zero, like `as` does.
+    Trunc,
+    Floor,
+    Ceil,
+    /// Rounds half-way cases away from zero.
+    Round,
+    /// Rounds half-way cases to the nearest even integer.
+    RoundTiesEven,
+}
+
+impl Rounding {
+    fn apply<F: Float>(self, f: F) -> F {
+        match self {
+            Rounding::Exact | Rounding::Trunc => f.trunc(),
+            Rounding::Floor => f.floor(),
+            Rounding::Ceil => f.ceil(),
+            Rounding::Round => f.round(),
+            Rounding::RoundTiesEven => {
+                let r = f.round();
+                let two = F::one() + F::one();
+                if (r - f).abs() == F::one() / two {
+                    two * (f / two).round()
+                } else {
+                    r
+                }
+            }
+        }
+    }
+}
+
+// Generates a checked conversion for every integer type. The bounds are powers of two (or zero),
+// so they are exact in every float type.
+macro_rules! to_int_checked {
+    ( $( $method_name: ident, $int: ident );* ) => {
+        $(
+            #[inline]
+            #[track_caller]
+            pub fn $method_name(self, rounding: Rounding) -> Result<$int, FloatError> {
+                let f = self.as_raw();
+                let r = rounding.apply(f);
+                if rounding == Rounding::Exact && r != f {
+                    return Err(FloatError::int_fractional(stringify!($int)));
+                }
+                // every float value has an f64 representation that compares the same
+                let wide = r.to_f64().unwrap_or(::core::f64::NAN);
+                if !(wide >= ::core::$int::MIN as f64 && wide < ::core::$int::MAX as f64 + 1.0) {
+                    return Err(FloatError::int_out_of_range(f, stringify!($int)));
+                }
+                Ok(wide as $int)
+            }
+        )*
+    }
+}
+
+impl<F> Clean<F>
+where
+    F: Float + NanPack<usize>,
+{
+    to_int_checked!(
+        to_i8_checked, i8;
+        to_i16_checked, i16;
+        to_i32_checked, i32;
+        to_i64_checked, i64;
+        to_isize_checked, isize;
+        to_u8_checked, u8;
+        to_u16_checked, u16;
+        to_u32_checked, u32;
+        to_u64_checked, u64;
+        to_usize_checked, usize
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {F32, F64};
+
+    #[test]
+    fn rounding() {
+        let f = F64::try_new(-2.5).unwrap();
+        assert!(f.to_i32_checked(Rounding::Exact).is_err());
+        assert_eq!(f.to_i32_checked(Rounding::Trunc).unwrap(), -2);
+        assert_eq!(f.to_i32_checked(Rounding::Floor).unwrap(), -3);
+        assert_eq!(f.to_i32_checked(Rounding::Ceil).unwrap(), -2);
+        assert_eq!(f.to_i32_checked(Rounding::Round).unwrap(), -3);
+        assert_eq!(f.to_i32_checked(Rounding::RoundTiesEven).unwrap(), -2);
+
+        let f = F32::try_new(3.5).unwrap();
+        assert_eq!(f.to_u8_checked(Rounding::RoundTiesEven).unwrap(), 4);
+        assert_eq!(f.to_u8_checked(Rounding::Trunc).unwrap(), 3);
+        let f = F64::try_new(7.0).unwrap();
+        assert_eq!(f.to_u64_checked(Rounding::Exact).unwrap(), 7);
+    }
+
+    #[test]
+    fn range() {
+        use std::f64::INFINITY as INF;
+
+        assert!(F64::try_new(INF).unwrap().to_i64_checked(Rounding::Trunc).is_err());
+        assert!(F64::try_new(-INF).unwrap().to_u8_checked(Rounding::Trunc).is_err());
+        assert!(F64::try_new(-1.0).unwrap().to_u32_checked(Rounding::Trunc).is_err());
+        assert_eq!(F64::try_new(-0.5).unwrap().to_u32_checked(Rounding::Trunc).unwrap(), 0);
+        assert_eq!(F64::try_new(255.0).unwrap().to_u8_checked(Rounding::Exact).unwrap(), 255);
+        assert!(F64::try_new(256.0).unwrap().to_u8_checked(Rounding::Exact).is_err());
+        assert_eq!(F64::try_new(-128.0).unwrap().to_i8_checked(Rounding::Exact).unwrap(), -128);
+        assert!(F64::try_new(-129.0).unwrap().to_i8_checked(Rounding::Exact).is_err());
+        let big = F64::try_new(9_223_372_036_854_775_808.0).unwrap();
+        assert!(big.to_i64_checked(Rounding::Exact).is_err());
+        assert_eq!((-big).to_i64_checked(Rounding::Exact).unwrap(), ::std::i64::MIN);
+    }
+
+    #[test]
+    fn from_int() {
+        assert_eq!(F64::from(::std::i32::MIN), ::std::i32::MIN as f64);
+        assert_eq!(F64::from(::std::u32::MAX), ::std::u32::MAX as f64);
+        assert_eq!(F32::from(-3i16), -3.0);
+    }
+}
@@ -0,0 +1,451 @@
+// Copyright 2017 Matthias Tellen
+//
+// Permission is hereby granted,  free of charge,  to any person  obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without  limitation  the  rights to use,  copy,  modify,  merge,  publish,  distribute,
+// sublicense,  and/or sell copies of the Software,  and to permit  persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The  above  copyright  notice and this permission notice shall be included  in all copies or sub-
+// stantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS",  WITHOUT WARRANTY OF ANY KIND,  EXPRESS OR IMPLIED,  INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,  FITNESS  FOR A PARTICULAR PURPOSE AND NON-
+// INFRINGEMENT.  IN NO EVENT SHALL THE AUTHORS  OR  COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAM-
+// AGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,  TORT OR OTHERWISE, ARISING FROM, OUT
+// OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Forward-mode automatic differentiation with dual numbers.
+//!
+//! A `Dual` carries a value and its derivative as two `Dirty` floats. The value behaves exactly
+//! like a `Dirty` would. When the derivative can't be computed at a point, e.g. for `sqrt` at
+//! zero, it becomes a NaN that records a "derivative undefined" error, which is independent of
+//! any error in the value.
+
+use core::ops::{Add, Div, Mul, Neg, Sub};
+use num_traits::float::Float;
+use super::{AsRaw, Clean, CleanFloat, Dirty, UncheckedConv};
+use ::error::FloatError;
+#[cfg(all(feature = "alloc", not(build = "release")))]
+use ::error::FLOAT_ERROR_BUFFER;
+use ::nanpack::NanPack;
+#[cfg(all(feature = "alloc", not(build = "release")))]
+use ::trait_impls::keep_first_error;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Dual<F: Float> {
+    value: Dirty<F>,
+    deriv: Dirty<F>,
+}
+
+// the NaN for a derivative that doesn't exist at `a`
+#[cfg(all(feature = "alloc", not(build = "release")))]
+#[inline]
+#[track_caller]
+fn undefined<F: Float + NanPack<usize>>(op: &'static str, a: F) -> F {
+    FLOAT_ERROR_BUFFER.insert(FloatError::derivative_undefined(op, a))
+}
+
+#[cfg(not(all(feature = "alloc", not(build = "release"))))]
+#[inline]
+fn undefined<F: Float + NanPack<usize>>(_op: &'static str, _a: F) -> F {
+    F::nan()
+}
+
+// the first derivative that already carries an error, the errors of the others are dropped
+#[cfg(all(feature = "alloc", not(build = "release")))]
+#[inline]
+fn payloaded<F: Float + NanPack<usize>>(derivs: &[F]) -> Option<F> {
+    let mut payloaded = derivs.iter().cloned().filter(|d| d.is_payloaded());
+    payloaded.next().map(|nan| payloaded.fold(nan, keep_first_error))
+}
+
+#[cfg(not(all(feature = "alloc", not(build = "release"))))]
+#[inline]
+fn payloaded<F: Float + NanPack<usize>>(_derivs: &[F]) -> Option<F> {
+    None
+}
+
+fn constant<F: Float>(f: f64) -> F {
+    F::from(f).unwrap_or_else(F::nan)
+}
+
+// a method whose derivative is `local` times the derivative of `self`. `$x` is bound to the
+// argument and `$y` to the result.
+macro_rules! dual_method {
+    ($name: ident, |$x: ident, $y: ident| $local: expr) => {
+        #[inline]
+        #[track_caller]
+        #[allow(unused_variables)]
+        pub fn $name(self) -> Self {
+            let $x = self.value.as_raw();
+            let value = CleanFloat::$name(self.value);
+            let $y = value.as_raw();
+            self.chain(stringify!($name), value, $local)
+        }
+    };
+}
+
+impl<F> Dual<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    pub fn new<V: AsRaw<F>, D: AsRaw<F>>(value: V, deriv: D) -> Self {
+        Dual {
+            value: Dirty::from_raw(value.as_raw()),
+            deriv: Dirty::from_raw(deriv.as_raw()),
+        }
+    }
+
+    /// The variable to differentiate by, i.e. with a derivative of one.
+    #[inline]
+    pub fn variable(x: Clean<F>) -> Self {
+        Dual::new(x, Dirty::from_raw(F::one()))
+    }
+
+    #[inline]
+    pub fn constant(x: Clean<F>) -> Self {
+        Dual::new(x, Dirty::from_raw(F::zero()))
+    }
+
+    #[inline]
+    pub fn value(self) -> Dirty<F> {
+        self.value
+    }
+
+    #[inline]
+    pub fn deriv(self) -> Dirty<F> {
+        self.deriv
+    }
+
+    /// Sanitizes the value and then the derivative.
+    #[inline]
+    pub fn sanitize(self) -> Result<(Clean<F>, Clean<F>), FloatError> {
+        Ok((self.value.sanitize()?, self.deriv.sanitize()?))
+    }
+
+    // the chain rule for a function with the derivative `local` at `self`
+    #[inline]
+    #[track_caller]
+    fn chain(self, op: &'static str, value: Dirty<F>, local: F) -> Self {
+        let (x, dx) = (self.value.as_raw(), self.deriv.as_raw());
+        let deriv = if let Some(nan) = payloaded(&[dx]) {
+            nan
+        } else if dx.is_zero() {
+            dx
+        } else if !local.is_finite() {
+            undefined(op, x)
+        } else {
+            let d = local * dx;
+            if d.is_nan() { undefined(op, x) } else { d }
+        };
+        Dual {
+            value,
+            deriv: Dirty::from_raw(deriv),
+        }
+    }
+
+    // checks a derivative that was computed from the derivatives `inputs`
+    #[inline]
+    #[track_caller]
+    fn combine(self, op: &'static str, value: Dirty<F>, inputs: &[F], deriv: F) -> Self {
+        let deriv = if let Some(nan) = payloaded(inputs) {
+            nan
+        } else if !deriv.is_finite() {
+            undefined(op, self.value.as_raw())
+        } else {
+            deriv
+        };
+        Dual {
+            value,
+            deriv: Dirty::from_raw(deriv),
+        }
+    }
+
+    dual_method!(floor, |x, y| F::zero());
+    dual_method!(ceil, |x, y| F::zero());
+    dual_method!(round, |x, y| F::zero());
+    dual_method!(fract, |x, y| F::one());
+    dual_method!(abs, |x, y| if x.is_zero() { F::nan() } else { x.signum() });
+    dual_method!(signum, |x, y| F::zero());
+    dual_method!(to_radians, |x, y| F::one().to_radians());
+    dual_method!(to_degrees, |x, y| F::one().to_degrees());
+    dual_method!(cbrt, |x, y| (constant::<F>(3.0) * y * y).recip());
+    dual_method!(sin, |x, y| x.cos());
+    dual_method!(cos, |x, y| -x.sin());
+    dual_method!(tan, |x, y| F::one() + y * y);
+    dual_method!(atan, |x, y| (F::one() + x * x).recip());
+    dual_method!(tanh, |x, y| F::one() - y * y);
+    dual_method!(recip, |x, y| -(x * x).recip());
+    dual_method!(sqrt, |x, y| (y + y).recip());
+    dual_method!(exp, |x, y| y);
+    dual_method!(exp2, |x, y| y * constant::<F>(2.0).ln());
+    dual_method!(ln, |x, y| x.recip());
+    dual_method!(log2, |x, y| (x * constant::<F>(2.0).ln()).recip());
+    dual_method!(log10, |x, y| (x * constant::<F>(10.0).ln()).recip());
+    dual_method!(acos, |x, y| -(F::one() - x * x).sqrt().recip());
+    dual_method!(asin, |x, y| (F::one() - x * x).sqrt().recip());
+    dual_method!(exp_m1, |x, y| x.exp());
+    dual_method!(ln_1p, |x, y| (F::one() + x).recip());
+    dual_method!(sinh, |x, y| x.cosh());
+    dual_method!(cosh, |x, y| x.sinh());
+    dual_method!(asinh, |x, y| (x * x + F::one()).sqrt().recip());
+    dual_method!(acosh, |x, y| (x * x - F::one()).sqrt().recip());
+    dual_method!(atanh, |x, y| (F::one() - x * x).recip());
+
+    #[inline]
+    #[track_caller]
+    pub fn sin_cos(self) -> (Self, Self) {
+        (self.sin(), self.cos())
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn powi(self, n: i32) -> Self {
+        let x = self.value.as_raw();
+        let local = if n == 0 {
+            F::zero()
+        } else {
+            constant::<F>(n as f64) * x.powi(n - 1)
+        };
+        self.chain("powi", CleanFloat::powi(self.value, n), local)
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn powf(self, exp: Self) -> Self {
+        let (x, dx) = (self.value.as_raw(), self.deriv.as_raw());
+        let (y, dy) = (exp.value.as_raw(), exp.deriv.as_raw());
+        let value = CleanFloat::powf(self.value, exp.value);
+        let mut deriv = if dx.is_zero() { F::zero() } else { y * x.powf(y - F::one()) * dx };
+        if !dy.is_zero() {
+            deriv = deriv + value.as_raw() * x.ln() * dy;
+        }
+        self.combine("powf", value, &[dx, dy], deriv)
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn log(self, base: Self) -> Self {
+        self.ln() / base.ln()
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn hypot(self, other: Self) -> Self {
+        let (x, dx) = (self.value.as_raw(), self.deriv.as_raw());
+        let (y, dy) = (other.value.as_raw(), other.deriv.as_raw());
+        let value = CleanFloat::hypot(self.value, other.value);
+        let deriv = (x * dx + y * dy) / value.as_raw();
+        self.combine("hypot", value, &[dx, dy], deriv)
+    }
+
+    /// The angle of `(other, self)`, like `Float::atan2`.
+    #[inline]
+    #[track_caller]
+    pub fn atan2(self, other: Self) -> Self {
+        let (y, dy) = (self.value.as_raw(), self.deriv.as_raw());
+        let (x, dx) = (other.value.as_raw(), other.deriv.as_raw());
+        let value = CleanFloat::atan2(self.value, other.value);
+        let deriv = (x * dy - y * dx) / (x * x + y * y);
+        self.combine("atan2", value, &[dx, dy], deriv)
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn mul_add(self, a: Self, b: Self) -> Self {
+        self * a + b
+    }
+}
+
+impl<F> From<Clean<F>> for Dual<F>
+where
+    F: Float + NanPack<usize>,
+{
+    /// Turns a `Clean` into a constant.
+    #[inline]
+    fn from(x: Clean<F>) -> Self {
+        Dual::constant(x)
+    }
+}
+
+impl<F> Add for Dual<F>
+where
+    F: Float + NanPack<usize>,
+{
+    type Output = Self;
+
+    #[inline]
+    #[track_caller]
+    fn add(self, other: Self) -> Self {
+        let (dx, dy) = (self.deriv.as_raw(), other.deriv.as_raw());
+        self.combine("addition", self.value + other.value, &[dx, dy], dx + dy)
+    }
+}
+
+impl<F> Sub for Dual<F>
+where
+    F: Float + NanPack<usize>,
+{
+    type Output = Self;
+
+    #[inline]
+    #[track_caller]
+    fn sub(self, other: Self) -> Self {
+        let (dx, dy) = (self.deriv.as_raw(), other.deriv.as_raw());
+        self.combine("subtraction", self.value - other.value, &[dx, dy], dx - dy)
+    }
+}
+
+impl<F> Mul for Dual<F>
+where
+    F: Float + NanPack<usize>,
+{
+    type Output = Self;
+
+    #[inline]
+    #[track_caller]
+    fn mul(self, other: Self) -> Self {
+        let (x, dx) = (self.value.as_raw(), self.deriv.as_raw());
+        let (y, dy) = (other.value.as_raw(), other.deriv.as_raw());
+        // skipping zero derivatives keeps infinite constants from turning them into NaN
+        let mut deriv = F::zero();
+        if !dx.is_zero() {
+            deriv = deriv + dx * y;
+        }
+        if !dy.is_zero() {
+            deriv = deriv + x * dy;
+        }
+        self.combine("multiplication", self.value * other.value, &[dx, dy], deriv)
+    }
+}
+
+impl<F> Div for Dual<F>
+where
+    F: Float + NanPack<usize>,
+{
+    type Output = Self;
+
+    #[inline]
+    #[track_caller]
+    fn div(self, other: Self) -> Self {
+        let (x, dx) = (self.value.as_raw(), self.deriv.as_raw());
+        let (y, dy) = (other.value.as_raw(), other.deriv.as_raw());
+        let value = self.value / other.value;
+        let mut deriv = F::zero();
+        if !dx.is_zero() {
+            deriv = deriv + dx / y;
+        }
+        if !dy.is_zero() {
+            deriv = deriv - x * dy / (y * y);
+        }
+        if y.is_zero() && !(dx.is_zero() && dy.is_zero()) {
+            // the quotient has a pole, not just a steep slope
+            deriv = F::nan();
+        }
+        self.combine("division", value, &[dx, dy], deriv)
+    }
+}
+
+impl<F> Neg for Dual<F>
+where
+    F: Float + NanPack<usize>,
+{
+    type Output = Self;
+
+    #[inline]
+    fn neg(self) -> Self {
+        Dual {
+            value: -self.value,
+            deriv: -self.deriv,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use F64;
+
+    fn var(x: f64) -> Dual<f64> {
+        Dual::variable(F64::try_new(x).unwrap())
+    }
+
+    fn cst(x: f64) -> Dual<f64> {
+        Dual::constant(F64::try_new(x).unwrap())
+    }
+
+    fn deriv(d: Dual<f64>) -> f64 {
+        d.sanitize().unwrap().1.as_raw()
+    }
+
+    #[test]
+    fn arithmetic() {
+        let x = var(3.0);
+        // d/dx (x² + 2x) / x = d/dx (x + 2) = 1
+        let f = (x * x + cst(2.0) * x) / x;
+        let (value, d) = f.sanitize().unwrap();
+        assert_eq!(value, 5.0);
+        assert!((d.as_raw() - 1.0).abs() < 1e-12);
+        assert_eq!(deriv(x - cst(1.0)), 1.0);
+        assert_eq!(deriv(-x), -1.0);
+        assert_eq!(deriv(x.mul_add(x, cst(1.0))), 6.0);
+    }
+
+    #[test]
+    fn functions() {
+        let x = var(0.5);
+        assert!((deriv(x.sin()) - 0.5f64.cos()).abs() < 1e-12);
+        assert!((deriv(x.exp()) - 0.5f64.exp()).abs() < 1e-12);
+        assert!((deriv(x.ln()) - 2.0).abs() < 1e-12);
+        assert!((deriv(x.sqrt()) - 1.0 / (2.0 * 0.5f64.sqrt())).abs() < 1e-12);
+        assert!((deriv(x.powi(3)) - 0.75).abs() < 1e-12);
+        assert!((deriv(x.powf(x)) - 0.5f64.powf(0.5) * (0.5f64.ln() + 1.0)).abs() < 1e-12);
+        assert!((deriv(x.atan2(cst(1.0))) - 1.0 / 1.25).abs() < 1e-12);
+        assert!((deriv(x.sin().powi(2) + x.cos().powi(2))).abs() < 1e-12);
+        assert_eq!(deriv(cst(0.0).sqrt()), 0.0);
+    }
+
+    #[test]
+    fn undefined_derivative() {
+        let f = var(0.0).sqrt();
+        assert_eq!(f.value().sanitize().unwrap(), 0.0);
+        let err = f.sanitize().unwrap_err();
+        #[cfg(all(feature = "alloc", not(build = "release")))]
+        assert!(format!("{}", err).ends_with("Derivative of sqrt at zero is undefined"));
+        let _ = err;
+        // the error follows the derivative, the value stays usable
+        let g = f * cst(2.0) + cst(1.0);
+        assert_eq!(g.value().sanitize().unwrap(), 1.0);
+        assert!(g.deriv().sanitize().is_err());
+        assert!(var(0.0).hypot(var(0.0)).deriv().sanitize().is_err());
+        assert!(var(0.0).abs().deriv().sanitize().is_err());
+        assert_eq!(deriv(var(-2.0).abs()), -1.0);
+        assert!(var(0.0).powf(cst(0.5)).deriv().sanitize().is_err());
+    }
+
+    #[test]
+    #[cfg(all(feature = "alloc", not(build = "release")))]
+    fn separate_errors() {
+        // the value and the derivative carry their own errors
+        let f = var(0.0) / cst(0.0);
+        let value_err = format!("{}", f.value().sanitize().unwrap_err());
+        let deriv_err = format!("{}", f.deriv().sanitize().unwrap_err());
+        assert!(value_err.ends_with("Division zero by zero resulted in NaN"));
+        assert!(deriv_err.ends_with("Derivative of division at zero is undefined"));
+
+        // operands that both carry errors pass one of them on
+        let g = f * f;
+        assert!(g.value().sanitize().is_err());
+        assert!(g.deriv().sanitize().is_err());
+        let (a, b) = (var(0.0) / cst(0.0), var(0.0) / cst(0.0));
+        let copy = b.deriv();
+        let h = a / b;
+        assert!(h.sanitize().is_err());
+        let err = copy.sanitize().unwrap_err();
+        #[cfg(all(feature = "alloc", not(build = "release")))]
+        assert!(format!("{}", err).ends_with("already retrieved or overwritten"));
+        let _ = err;
+    }
+}
@@ -15,19 +15,37 @@
 // AGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,  TORT OR OTHERWISE, ARISING FROM, OUT
 // OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-use std::fmt;
-use std::sync::Mutex;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::sync::{Mutex, MutexGuard};
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+use spin::{Lazy, Mutex, MutexGuard};
+#[cfg(feature = "alloc")]
+use core::sync::atomic::{AtomicBool, Ordering};
 use num_traits::float::Float;
-use std::collections::BTreeMap;
-use std::num::FpCategory;
+#[cfg(feature = "alloc")]
+use alloc::collections::BTreeMap;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::num::FpCategory;
+use core::panic::Location;
+#[cfg(feature = "std")]
 use backtrace;
+#[cfg(feature = "alloc")]
+use nanpack::NanPack;
 
 
-#[cfg(not(build = "release"))]
+// The error buffer needs `alloc`, and a spin lock guards it when `std` is disabled. The capturing
+// of the location of an error needs `std`. Without `alloc`, NaNs don't carry a payload, and
+// without `std` errors don't know where they happened.
+#[cfg(all(feature = "std", not(build = "release")))]
 lazy_static! {
     pub(crate) static ref FLOAT_ERROR_BUFFER: ErrorBuffer = Default::default();
 }
 
+#[cfg(all(feature = "alloc", not(feature = "std"), not(build = "release")))]
+pub(crate) static FLOAT_ERROR_BUFFER: Lazy<ErrorBuffer> = Lazy::new(ErrorBuffer::default);
+
 #[derive(Debug, Eq, PartialEq)]
 pub(crate) enum FloatClass {
     PlusZero,
@@ -74,42 +92,223 @@ impl fmt::Display for FloatClass {
 // bucket is choosen at random, the lock is aquired and the error is stored.
 // To make the index unique and the bucket identifiyable via the index, it's
 // shifted 4 bits to the left and the bucket index is safed in these 4 bits.
+//
+// The payload of a NaN is split into a slot index and a tag. The slot index
+// wraps around once the payload capacity is reached, and the tag (the number of
+// times it has wrapped) tells a live error apart from an old NaN that still
+// refers to the same slot. If every slot is taken, the exhausted NaN is used.
+#[cfg(feature = "alloc")]
 pub(crate) struct ErrorBuffer {
     bucket: Mutex<ErrorBufferBucket>,
 }
 
+#[cfg(feature = "alloc")]
 pub(crate) struct ErrorBufferBucket {
     // is incremented for every new error
     index: usize,
-    errors: BTreeMap<usize, FloatError>,
+    // how many of the payload bits are used for the tag
+    tag_bits: u32,
+    // maps the slot to the tag it was stored with and the error
+    errors: BTreeMap<usize, (usize, FloatError)>,
+    // overflows and underflows, which don't poison the value
+    warnings: Vec<FloatError>,
+}
+
+#[cfg(feature = "alloc")]
+const DEFAULT_TAG_BITS: u32 = 4;
+
+// warnings beyond this are dropped until the warnings are taken
+#[cfg(feature = "alloc")]
+const MAX_WARNINGS: usize = 1024;
+
+/// The operations that are checked for results outside of the normal range.
+#[cfg(all(feature = "alloc", not(build = "release")))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum RangeOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[cfg(all(feature = "alloc", not(build = "release")))]
+impl RangeOp {
+    fn name(self) -> &'static str {
+        match self {
+            RangeOp::Add => "Addition",
+            RangeOp::Sub => "Subtraction",
+            RangeOp::Mul => "Multiplication",
+            RangeOp::Div => "Division",
+        }
+    }
+
+    // true if `result` is exactly `a op b`, for a result below the normal range. Sums are always
+    // exact there. Otherwise the operands are small enough to scale the check into the normal
+    // range, where `mul_add` computes the rounding error without rounding it away.
+    fn is_exact<F: Float>(self, a: F, b: F, result: F) -> bool {
+        let scale = (F::one() + F::one()) / F::epsilon();
+        match self {
+            RangeOp::Add | RangeOp::Sub => true,
+            RangeOp::Mul => {
+                let (small, large) = if a.abs() <= b.abs() { (a, b) } else { (b, a) };
+                (small * scale).mul_add(large, -(result * scale)).is_zero()
+            }
+            RangeOp::Div => (result * scale).mul_add(b, -(a * scale)).is_zero(),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+static RANGE_WARNINGS: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables warnings for arithmetic that overflows to infinity or underflows to zero
+/// or a subnormal value. The warnings are collected by `Dirty::warnings`. Disabled by default.
+#[cfg(feature = "alloc")]
+pub fn set_range_warnings(enabled: bool) {
+    RANGE_WARNINGS.store(enabled, Ordering::Relaxed)
+}
+
+#[cfg(feature = "alloc")]
+#[inline]
+pub(crate) fn range_warnings_enabled() -> bool {
+    RANGE_WARNINGS.load(Ordering::Relaxed)
 }
 
+#[cfg(feature = "alloc")]
 impl ErrorBuffer {
-    pub(crate) fn insert(&self, error: FloatError) -> usize {
-        let mut bucket = self.bucket.lock().unwrap();
-        bucket.index += 1;
-        let index = bucket.index;
-        bucket.errors.insert(index, error);
-        bucket.index
+    #[cfg(feature = "std")]
+    fn lock(&self) -> MutexGuard<'_, ErrorBufferBucket> {
+        self.bucket.lock().unwrap()
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn lock(&self) -> MutexGuard<'_, ErrorBufferBucket> {
+        self.bucket.lock()
     }
 
-    pub(crate) fn remove(&self, index: usize) -> FloatError {
-        let mut bucket = self.bucket.lock().unwrap();
-        bucket.errors.remove(&index).expect("error in error buffer")
+    /// Stores the error and returns a NaN carrying its payload
+    pub(crate) fn insert<F: NanPack<usize>>(&self, error: FloatError) -> F {
+        match self.insert_with_capacity(error, F::capacity()) {
+            Some(payload) => F::set_payload(payload),
+            None => F::exhausted(),
+        }
+    }
+
+    pub(crate) fn insert_with_capacity(&self, error: FloatError, capacity: usize) -> Option<usize> {
+        let mut bucket = self.lock();
+        let (slots, tags) = split_capacity(capacity, bucket.tag_bits);
+        if slots == 0 || bucket.errors.len() >= slots {
+            return None;
+        }
+        // errors stored with a bigger capacity may occupy slots outside of our range, so the
+        // search is bounded
+        for _ in 0..slots {
+            bucket.index = bucket.index.wrapping_add(1);
+            let slot = bucket.index % slots;
+            if !bucket.errors.contains_key(&slot) {
+                let tag = (bucket.index / slots) % tags;
+                bucket.errors.insert(slot, (tag, error));
+                return Some(tag * slots + slot);
+            }
+        }
+        None
+    }
+
+    pub(crate) fn remove<F: NanPack<usize>>(&self, payload: usize) -> Option<FloatError> {
+        self.remove_with_capacity(payload, F::capacity())
+    }
+
+    /// Moves the error a NaN refers to into a payload of another float type. The sign of the
+    /// NaN is kept.
+    pub(crate) fn remap<A, B>(&self, nan: A) -> B
+    where
+        A: NanPack<usize> + Copy,
+        B: NanPack<usize>,
+    {
+        let meta = nan.get_meta().unwrap_or_default();
+        if nan.is_exhausted() {
+            return B::set_payload_with_meta(B::capacity(), meta);
+        }
+        let error = match nan.get_payload().and_then(|payload| self.remove::<A>(payload)) {
+            Some(error) => error,
+            None => FloatError::expired(),
+        };
+        match self.insert_with_capacity(error, B::capacity()) {
+            Some(payload) => B::set_payload_with_meta(payload, meta),
+            None => B::set_payload_with_meta(B::capacity(), meta),
+        }
+    }
+
+    pub(crate) fn remove_with_capacity(
+        &self,
+        payload: usize,
+        capacity: usize,
+    ) -> Option<FloatError> {
+        let mut bucket = self.lock();
+        let (slots, _) = split_capacity(capacity, bucket.tag_bits);
+        if slots == 0 {
+            return None;
+        }
+        let (tag, slot) = (payload / slots, payload % slots);
+        match bucket.errors.get(&slot) {
+            Some(&(stored_tag, _)) if stored_tag == tag => {}
+            _ => return None,
+        }
+        bucket.errors.remove(&slot).map(|(_, error)| error)
+    }
+
+    pub(crate) fn set_tag_bits(&self, tag_bits: u32) {
+        let mut bucket = self.lock();
+        bucket.tag_bits = tag_bits;
+        // payloads built with the old split could match the wrong entries
+        bucket.errors.clear();
+    }
+
+    pub(crate) fn warn(&self, warning: FloatError) {
+        let mut bucket = self.lock();
+        if bucket.warnings.len() < MAX_WARNINGS {
+            bucket.warnings.push(warning);
+        }
+    }
+
+    pub(crate) fn take_warnings(&self) -> Vec<FloatError> {
+        let mut bucket = self.lock();
+        ::core::mem::take(&mut bucket.warnings)
     }
 }
 
+// returns the number of slots and tags for a payload capacity
+#[cfg(feature = "alloc")]
+fn split_capacity(capacity: usize, tag_bits: u32) -> (usize, usize) {
+    let tags = 1usize.checked_shl(tag_bits).unwrap_or(0);
+    if tags == 0 {
+        return (0, 0);
+    }
+    (capacity / tags, tags)
+}
+
+#[cfg(feature = "alloc")]
 impl Default for ErrorBuffer {
     fn default() -> Self {
         ErrorBuffer {
             bucket: Mutex::new(ErrorBufferBucket {
                 index: 0,
+                tag_bits: DEFAULT_TAG_BITS,
                 errors: BTreeMap::new(),
+                warnings: Vec::new(),
             }),
         }
     }
 }
 
+/// Sets how many bits of a NaN payload are used to detect stale NaNs. The remaining bits are
+/// used to index the stored errors, so more tag bits mean fewer errors that can be alive at
+/// the same time. Changing this invalidates all errors that are currently stored.
+#[cfg(feature = "alloc")]
+pub fn set_payload_tag_bits(tag_bits: u32) {
+    FLOAT_ERROR_BUFFER.set_tag_bits(tag_bits)
+}
+
 #[derive(Fail, PartialEq)]
 #[fail(display = "{}: {}", debug_info, variant)]
 pub struct FloatError {
@@ -123,28 +322,91 @@ impl fmt::Debug for FloatError {
     }
 }
 
+// without `std`, the variants that need the error buffer are never constructed
+#[cfg_attr(not(feature = "alloc"), allow(dead_code))]
 #[derive(Fail, Debug, PartialEq)]
 pub(crate) enum FloatErrorInner {
+    #[fail(display = "Addition of {} and {} resulted in NaN", a, b)]
+    Add { a: FloatClass, b: FloatClass },
+    #[fail(display = "Subtraction of {} minus {} resulted in NaN", a, b)]
+    Sub { a: FloatClass, b: FloatClass },
     #[fail(display = "Division {} by {} resulted in NaN", a, b)]
     Div { a: FloatClass, b: FloatClass },
     #[fail(display = "Multiplication {} by {} resulted in NaN", a, b)]
     Mul { a: FloatClass, b: FloatClass },
+    #[fail(display = "Remainder of {} divided by {} resulted in NaN", a, b)]
+    Rem { a: FloatClass, b: FloatClass },
+    #[fail(display = "Power of {} to {} resulted in NaN", a, b)]
+    Pow { a: FloatClass, b: FloatClass },
+    #[fail(display = "{} of {} resulted in NaN", op, a)]
+    Function { op: &'static str, a: FloatClass },
     #[fail(display = "Sanitization of {}", a)]
     Sanitization { a: FloatClass },
+    #[fail(display = "NaN from an operation that happened while the error buffer was full")]
+    BufferExhausted,
+    #[fail(display = "NaN refers to an error that was already retrieved or overwritten")]
+    Expired,
+    #[fail(display = "Narrowing {} to f32 overflowed", a)]
+    NarrowingOverflow { a: FloatClass },
+    #[fail(display = "Narrowing {} to f32 lost precision", a)]
+    NarrowingPrecisionLoss { a: FloatClass },
+    #[fail(display = "Conversion of {} to {} is out of range", a, target)]
+    IntOutOfRange { a: FloatClass, target: &'static str },
+    #[fail(display = "Conversion of a fractional value to {} is not exact", target)]
+    IntFractional { target: &'static str },
+    #[fail(display = "Interval from {} to {} is invalid", lo, hi)]
+    IntervalBounds { lo: FloatClass, hi: FloatClass },
+    #[fail(display = "{} of an interval outside of its domain", op)]
+    IntervalDomain { op: &'static str },
+    #[fail(display = "Complex {} of {} and {} resulted in NaN", op, a, b)]
+    ComplexBinary { op: &'static str, a: FloatClass, b: FloatClass },
+    #[fail(display = "Complex {} of {} resulted in NaN", op, a)]
+    ComplexUnary { op: &'static str, a: FloatClass },
+    #[fail(display = "Derivative of {} at {} is undefined", op, a)]
+    DerivativeUndefined { op: &'static str, a: FloatClass },
+    #[fail(display = "Invalid {} {} for the {} distribution", parameter, a, distribution)]
+    #[cfg_attr(not(feature = "rand"), allow(dead_code))]
+    InvalidDistribution {
+        distribution: &'static str,
+        parameter: &'static str,
+        a: FloatClass,
+    },
+    #[fail(display = "Angle of {} is not finite", a)]
+    NonFiniteAngle { a: FloatClass },
+    #[fail(display = "{} overflowed to infinity", op)]
+    Overflow { op: &'static str },
+    #[fail(display = "{} underflowed to zero", op)]
+    UnderflowToZero { op: &'static str },
+    #[fail(display = "{} underflowed to a subnormal value", op)]
+    UnderflowToSubnormal { op: &'static str },
 }
 
 #[derive(Debug, PartialEq)]
 pub(crate) struct DebugInfo {
+    #[cfg(feature = "std")]
     lineno: u32,
+    #[cfg(feature = "std")]
     filename: String,
 }
 
 impl fmt::Display for DebugInfo {
+    #[cfg(feature = "std")]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}:{}", self.filename, self.lineno)
     }
+
+    #[cfg(not(feature = "std"))]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown location")
+    }
+}
+
+#[cfg(not(feature = "std"))]
+fn get_caller_debug_info(_depth: usize) -> DebugInfo {
+    DebugInfo {}
 }
 
+#[cfg(feature = "std")]
 fn get_caller_debug_info(mut depth: usize) -> DebugInfo {
     let mut debug_info = DebugInfo {
         lineno: 0,
@@ -170,11 +432,48 @@ fn get_caller_debug_info(mut depth: usize) -> DebugInfo {
 }
 const STACKTRACE_DEPTH: usize = 5;
 
+// errors whose public entry points are `#[track_caller]` take the location from there instead of
+// walking the stack
+#[cfg(not(feature = "std"))]
+fn location_debug_info(_location: &'static Location<'static>) -> DebugInfo {
+    DebugInfo {}
+}
+
+#[cfg(feature = "std")]
+fn location_debug_info(location: &'static Location<'static>) -> DebugInfo {
+    DebugInfo {
+        lineno: location.line(),
+        filename: location.file().into(),
+    }
+}
+
 impl FloatError {
-    #[cfg(not(build = "release"))]
+    #[track_caller]
+    pub(crate) fn add<F: Into<FloatClass>>(a: F, b: F) -> Self {
+        FloatError {
+            debug_info: location_debug_info(Location::caller()),
+            variant: FloatErrorInner::Add {
+                a: a.into(),
+                b: b.into(),
+            },
+        }
+    }
+
+    #[track_caller]
+    pub(crate) fn sub<F: Into<FloatClass>>(a: F, b: F) -> Self {
+        FloatError {
+            debug_info: location_debug_info(Location::caller()),
+            variant: FloatErrorInner::Sub {
+                a: a.into(),
+                b: b.into(),
+            },
+        }
+    }
+
+    #[track_caller]
     pub(crate) fn div<F: Into<FloatClass>>(a: F, b: F) -> Self {
         FloatError {
-            debug_info: get_caller_debug_info(STACKTRACE_DEPTH),
+            debug_info: location_debug_info(Location::caller()),
             variant: FloatErrorInner::Div {
                 a: a.into(),
                 b: b.into(),
@@ -182,9 +481,10 @@ impl FloatError {
         }
     }
 
+    #[track_caller]
     pub(crate) fn mul<F: Into<FloatClass>>(a: F, b: F) -> Self {
         FloatError {
-            debug_info: get_caller_debug_info(STACKTRACE_DEPTH),
+            debug_info: location_debug_info(Location::caller()),
             variant: FloatErrorInner::Mul {
                 a: a.into(),
                 b: b.into(),
@@ -192,6 +492,60 @@ impl FloatError {
         }
     }
 
+    #[track_caller]
+    pub(crate) fn rem<F: Into<FloatClass>>(a: F, b: F) -> Self {
+        FloatError {
+            debug_info: location_debug_info(Location::caller()),
+            variant: FloatErrorInner::Rem {
+                a: a.into(),
+                b: b.into(),
+            },
+        }
+    }
+
+    #[track_caller]
+    pub(crate) fn pow<F: Into<FloatClass>>(a: F, b: F) -> Self {
+        FloatError {
+            debug_info: location_debug_info(Location::caller()),
+            variant: FloatErrorInner::Pow {
+                a: a.into(),
+                b: b.into(),
+            },
+        }
+    }
+
+    #[track_caller]
+    pub(crate) fn function<F: Into<FloatClass>>(op: &'static str, a: F) -> Self {
+        FloatError {
+            debug_info: location_debug_info(Location::caller()),
+            variant: FloatErrorInner::Function { op, a: a.into() },
+        }
+    }
+
+    #[cfg(feature = "rand")]
+    #[track_caller]
+    pub(crate) fn invalid_distribution<F: Into<FloatClass>>(
+        distribution: &'static str,
+        parameter: &'static str,
+        a: F,
+    ) -> Self {
+        FloatError {
+            debug_info: location_debug_info(Location::caller()),
+            variant: FloatErrorInner::InvalidDistribution {
+                distribution,
+                parameter,
+                a: a.into(),
+            },
+        }
+    }
+
+    #[track_caller]
+    pub(crate) fn non_finite_angle<F: Into<FloatClass>>(a: F) -> Self {
+        FloatError {
+            debug_info: location_debug_info(Location::caller()),
+            variant: FloatErrorInner::NonFiniteAngle { a: a.into() },
+        }
+    }
 
     pub(crate) fn sanitization<F: Into<FloatClass>>(a: F) -> Self {
         FloatError {
@@ -200,6 +554,125 @@ impl FloatError {
         }
     }
 
+    #[cfg(feature = "alloc")]
+    pub(crate) fn buffer_exhausted() -> Self {
+        FloatError {
+            debug_info: get_caller_debug_info(STACKTRACE_DEPTH),
+            variant: FloatErrorInner::BufferExhausted,
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn expired() -> Self {
+        FloatError {
+            debug_info: get_caller_debug_info(STACKTRACE_DEPTH),
+            variant: FloatErrorInner::Expired,
+        }
+    }
+
+    #[track_caller]
+    pub(crate) fn narrowing_overflow<F: Into<FloatClass>>(a: F) -> Self {
+        FloatError {
+            debug_info: location_debug_info(Location::caller()),
+            variant: FloatErrorInner::NarrowingOverflow { a: a.into() },
+        }
+    }
+
+    #[track_caller]
+    pub(crate) fn narrowing_precision_loss<F: Into<FloatClass>>(a: F) -> Self {
+        FloatError {
+            debug_info: location_debug_info(Location::caller()),
+            variant: FloatErrorInner::NarrowingPrecisionLoss { a: a.into() },
+        }
+    }
+
+    #[track_caller]
+    pub(crate) fn int_out_of_range<F: Into<FloatClass>>(a: F, target: &'static str) -> Self {
+        FloatError {
+            debug_info: location_debug_info(Location::caller()),
+            variant: FloatErrorInner::IntOutOfRange {
+                a: a.into(),
+                target,
+            },
+        }
+    }
+
+    #[track_caller]
+    pub(crate) fn int_fractional(target: &'static str) -> Self {
+        FloatError {
+            debug_info: location_debug_info(Location::caller()),
+            variant: FloatErrorInner::IntFractional { target },
+        }
+    }
+
+    #[track_caller]
+    pub(crate) fn interval_bounds<F: Into<FloatClass>>(lo: F, hi: F) -> Self {
+        FloatError {
+            debug_info: location_debug_info(Location::caller()),
+            variant: FloatErrorInner::IntervalBounds {
+                lo: lo.into(),
+                hi: hi.into(),
+            },
+        }
+    }
+
+    #[track_caller]
+    pub(crate) fn interval_domain(op: &'static str) -> Self {
+        FloatError {
+            debug_info: location_debug_info(Location::caller()),
+            variant: FloatErrorInner::IntervalDomain { op },
+        }
+    }
+
+    #[cfg(all(feature = "alloc", not(build = "release")))]
+    #[track_caller]
+    pub(crate) fn complex_binary(op: &'static str, a: FloatClass, b: FloatClass) -> Self {
+        FloatError {
+            debug_info: location_debug_info(Location::caller()),
+            variant: FloatErrorInner::ComplexBinary { op, a, b },
+        }
+    }
+
+    #[cfg(all(feature = "alloc", not(build = "release")))]
+    #[track_caller]
+    pub(crate) fn complex_unary(op: &'static str, a: FloatClass) -> Self {
+        FloatError {
+            debug_info: location_debug_info(Location::caller()),
+            variant: FloatErrorInner::ComplexUnary { op, a },
+        }
+    }
+
+    #[cfg(all(feature = "alloc", not(build = "release")))]
+    #[track_caller]
+    pub(crate) fn derivative_undefined<F: Into<FloatClass>>(op: &'static str, a: F) -> Self {
+        FloatError {
+            debug_info: location_debug_info(Location::caller()),
+            variant: FloatErrorInner::DerivativeUndefined { op, a: a.into() },
+        }
+    }
+
+    /// The warning for an operation on finite, non-zero operands whose result left the normal
+    /// range. Results below it are only reported if they were rounded.
+    #[cfg(all(feature = "alloc", not(build = "release")))]
+    pub(crate) fn range<F: Float>(op: RangeOp, a: F, b: F, result: F) -> Option<Self> {
+        if !a.is_finite() || !b.is_finite() || b.is_zero() {
+            return None;
+        }
+        let variant = if result.is_infinite() {
+            FloatErrorInner::Overflow { op: op.name() }
+        } else if result.is_zero() && !a.is_zero() && !matches!(op, RangeOp::Add | RangeOp::Sub) {
+            // sums only reach zero by cancelling exactly, like `x - x`
+            FloatErrorInner::UnderflowToZero { op: op.name() }
+        } else if result.classify() == FpCategory::Subnormal && !op.is_exact(a, b, result) {
+            FloatErrorInner::UnderflowToSubnormal { op: op.name() }
+        } else {
+            return None;
+        };
+        Some(FloatError {
+            debug_info: get_caller_debug_info(STACKTRACE_DEPTH),
+            variant,
+        })
+    }
 
     /*
     pub fn new() -> Self {
@@ -264,7 +737,15 @@ impl FloatError {
 #[cfg(test)]
 mod tests {
     use super::super::*;
-    use super::{FloatClass, FloatErrorInner};
+    #[cfg(feature = "alloc")]
+    use super::{ErrorBuffer, FloatClass, FloatErrorInner};
+    #[cfg(all(feature = "alloc", not(build = "release")))]
+    use super::RangeOp;
+
+    #[cfg(feature = "alloc")]
+    fn dummy_error() -> FloatError {
+        FloatError::sanitization(0.0)
+    }
 
 
     #[test]
@@ -277,6 +758,7 @@ mod tests {
 
 
     #[test]
+    #[cfg(feature = "alloc")]
     fn bad_operation() {
         let a = F64::try_new(0.0).unwrap();
         let b = F64::try_new(0.0).unwrap();
@@ -292,6 +774,15 @@ mod tests {
         );
 
 
+        let err = (a % b).sanitize().err().unwrap();
+        assert_eq!(
+            FloatErrorInner::Rem {
+                a: FloatClass::PlusZero,
+                b: FloatClass::PlusZero,
+            },
+            err.variant
+        );
+
         let err = F64::try_new(std::f64::NAN).err().unwrap();
         assert_eq!(
             FloatErrorInner::Sanitization { a: FloatClass::NaN },
@@ -300,5 +791,130 @@ mod tests {
 
     }
 
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn two_tracked_nans() {
+        let z = F64::try_new(0.0).unwrap();
+        let n = z / z;
+        let err = (n % n).sanitize().err().unwrap();
+        assert_eq!(
+            FloatErrorInner::Div {
+                a: FloatClass::PlusZero,
+                b: FloatClass::PlusZero,
+            },
+            err.variant
+        );
+        let err = ((z / z) % (z % z)).sanitize().err().unwrap();
+        assert!(matches!(err.variant, FloatErrorInner::Div { .. }));
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn range_warnings() {
+        use {AsRaw, DirtyF64};
+
+        let max = F64::try_new(std::f64::MAX).unwrap();
+        let tiny = F64::try_new(1e-300).unwrap();
+        set_range_warnings(true);
+        let overflow = max + max;
+        let to_zero = tiny * tiny;
+        let to_subnormal = tiny / F64::try_new(1e10).unwrap();
+        let min = F64::try_new(std::f64::MIN_POSITIVE).unwrap();
+        let exact = min / F64::try_new(4.0).unwrap();
+        set_range_warnings(false);
+
+        // no poisoning, the values are what the hardware produced
+        assert_eq!(overflow.as_raw(), std::f64::INFINITY);
+        assert_eq!(to_zero.as_raw(), 0.0);
+        assert!(to_subnormal.as_raw() > 0.0);
+        assert_eq!(exact.as_raw(), std::f64::MIN_POSITIVE / 4.0);
+        let variants: Vec<_> = DirtyF64::warnings().into_iter().map(|w| w.variant).collect();
+        // other tests may run arithmetic at the same time, so only the presence of our warnings
+        // is checked on the buffer, and the absence of others with `FloatError::range`
+        assert!(variants.contains(&FloatErrorInner::Overflow { op: "Addition" }));
+        assert!(variants.contains(&FloatErrorInner::UnderflowToZero { op: "Multiplication" }));
+        assert!(variants.contains(&FloatErrorInner::UnderflowToSubnormal { op: "Division" }));
+        let max = std::f64::MAX;
+        assert!(FloatError::range(RangeOp::Div, max, 0.0, max / 0.0).is_none());
+        assert!(FloatError::range(RangeOp::Add, 1e-300, 1e-300, 1e-300 + 1e-300).is_none());
+
+        // cancellation is exact
+        assert!(FloatError::range(RangeOp::Sub, 1.0, 1.0, 1.0 - 1.0).is_none());
+        assert!(FloatError::range(RangeOp::Add, 1.0, -1.0, 1.0 + -1.0).is_none());
+        let min = std::f64::MIN_POSITIVE;
+        assert!(FloatError::range(RangeOp::Sub, min, min / 2.0, min - min / 2.0).is_none());
+
+        // subnormal results that weren't rounded are fine
+        assert!(FloatError::range(RangeOp::Div, min, 4.0, min / 4.0).is_none());
+        assert!(FloatError::range(RangeOp::Mul, min, 0.25, min * 0.25).is_none());
+        assert!(FloatError::range(RangeOp::Div, min, 3.0, min / 3.0).is_some());
+        assert!(FloatError::range(RangeOp::Mul, 1e-300, 1e-10, 1e-300 * 1e-10).is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn buffer_wraps_around() {
+        let buffer = ErrorBuffer::default();
+        buffer.set_tag_bits(1);
+        // 4 slots with 2 tags each
+        for _ in 0..20 {
+            let payload = buffer.insert_with_capacity(dummy_error(), 8).unwrap();
+            assert!(payload < 8);
+            assert!(buffer.remove_with_capacity(payload, 8).is_some());
+            assert!(buffer.remove_with_capacity(payload, 8).is_none());
+        }
+    }
 
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn buffer_exhausted() {
+        let buffer = ErrorBuffer::default();
+        buffer.set_tag_bits(1);
+        let payloads: Vec<_> = (0..4)
+            .map(|_| buffer.insert_with_capacity(dummy_error(), 8).unwrap())
+            .collect();
+        assert!(buffer.insert_with_capacity(dummy_error(), 8).is_none());
+        assert!(buffer.remove_with_capacity(payloads[2], 8).is_some());
+        assert!(buffer.insert_with_capacity(dummy_error(), 8).is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn stale_payload() {
+        let buffer = ErrorBuffer::default();
+        buffer.set_tag_bits(1);
+        let old = buffer.insert_with_capacity(dummy_error(), 8).unwrap();
+        buffer.remove_with_capacity(old, 8).unwrap();
+        // fill all slots until the old slot is reused with a different tag
+        let new: Vec<_> = (0..4)
+            .map(|_| buffer.insert_with_capacity(dummy_error(), 8).unwrap())
+            .collect();
+        assert!(!new.contains(&old));
+        assert!(buffer.remove_with_capacity(old, 8).is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn tag_bits_changed() {
+        let buffer = ErrorBuffer::default();
+        buffer.set_tag_bits(1);
+        let old: Vec<_> = (0..4)
+            .map(|_| buffer.insert_with_capacity(dummy_error(), 8).unwrap())
+            .collect();
+        buffer.set_tag_bits(2);
+        for &payload in &old {
+            assert!(buffer.remove_with_capacity(payload, 8).is_none());
+        }
+        // the old errors don't take up slots anymore
+        assert!(buffer.insert_with_capacity(dummy_error(), 8).is_some());
+        assert!(buffer.insert_with_capacity(dummy_error(), 8).is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn exhausted_nan() {
+        let exhausted: Dirty<f32> = Dirty::new(NanPack::exhausted());
+        let err = exhausted.sanitize().err().unwrap();
+        assert_eq!(FloatErrorInner::BufferExhausted, err.variant);
+    }
 }
@@ -0,0 +1,179 @@
+// Copyright 2017 Matthias Tellen
+//
+// Permission is hereby granted,  free of charge,  to any person  obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without  limitation  the  rights to use,  copy,  modify,  merge,  publish,  distribute,
+// sublicense,  and/or sell copies of the Software,  and to permit  persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The  above  copyright  notice and this permission notice shall be included  in all copies or sub-
+// stantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS",  WITHOUT WARRANTY OF ANY KIND,  EXPRESS OR IMPLIED,  INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,  FITNESS  FOR A PARTICULAR PURPOSE AND NON-
+// INFRINGEMENT.  IN NO EVENT SHALL THE AUTHORS  OR  COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAM-
+// AGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,  TORT OR OTHERWISE, ARISING FROM, OUT
+// OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Detection of invalid operations through the floating point exception flags of the hardware.
+//!
+//! This is a cheap complement to tracking every operation: `guarded` clears the flags, runs a
+//! closure and reports which exceptions the closure raised. Only available on Linux on x86_64
+//! and aarch64, where the flags are reached through `fetestexcept` and `feclearexcept`.
+//!
+//! The compiler assumes that floating point operations have no side effects, so operations on
+//! constants can be folded at compile time and never raise a flag.
+
+use core::fmt;
+use failure::Fail;
+
+#[cfg(target_arch = "x86_64")]
+mod flags {
+    pub const FE_INVALID: i32 = 0x01;
+    pub const FE_DIVBYZERO: i32 = 0x04;
+    pub const FE_OVERFLOW: i32 = 0x08;
+}
+
+#[cfg(target_arch = "aarch64")]
+mod flags {
+    pub const FE_INVALID: i32 = 0x01;
+    pub const FE_DIVBYZERO: i32 = 0x02;
+    pub const FE_OVERFLOW: i32 = 0x04;
+}
+
+use self::flags::{FE_DIVBYZERO, FE_INVALID, FE_OVERFLOW};
+
+const FE_GUARDED: i32 = FE_INVALID | FE_DIVBYZERO | FE_OVERFLOW;
+
+#[link(name = "m")]
+extern "C" {
+    fn fetestexcept(excepts: i32) -> i32;
+    fn feclearexcept(excepts: i32) -> i32;
+    fn feraiseexcept(excepts: i32) -> i32;
+}
+
+/// The exceptions that were raised in a `guarded` block.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct FpExceptions {
+    /// An operation without a defined result produced a NaN, e.g. `0 / 0` or `inf - inf`.
+    pub invalid: bool,
+    /// A finite value was divided by zero.
+    pub div_by_zero: bool,
+    /// A finite result was too large and became infinite.
+    pub overflow: bool,
+}
+
+impl FpExceptions {
+    fn from_bits(bits: i32) -> Self {
+        FpExceptions {
+            invalid: bits & FE_INVALID != 0,
+            div_by_zero: bits & FE_DIVBYZERO != 0,
+            overflow: bits & FE_OVERFLOW != 0,
+        }
+    }
+}
+
+impl fmt::Display for FpExceptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = [
+            (self.invalid, "invalid operation"),
+            (self.div_by_zero, "division by zero"),
+            (self.overflow, "overflow"),
+        ];
+        write!(f, "Floating point exception:")?;
+        let mut first = true;
+        for &(_, name) in names.iter().filter(|&&(raised, _)| raised) {
+            write!(f, "{} {}", if first { "" } else { "," }, name)?;
+            first = false;
+        }
+        Ok(())
+    }
+}
+
+impl Fail for FpExceptions {}
+
+/// Runs `c` and fails if it raised an invalid operation, a division by zero or an overflow.
+///
+/// Flags that were raised before the call are restored afterwards, together with the ones
+/// raised by `c`, so an enclosing `guarded` still sees them.
+pub fn guarded<T, C: FnOnce() -> T>(c: C) -> Result<T, FpExceptions> {
+    let _restore = RestoreFlags(unsafe { fetestexcept(FE_GUARDED) });
+    let (raised, result) = unsafe {
+        feclearexcept(FE_GUARDED);
+        let result = c();
+        (fetestexcept(FE_GUARDED), result)
+    };
+    if raised != 0 {
+        return Err(FpExceptions::from_bits(raised));
+    }
+    Ok(result)
+}
+
+// raises the flags from before a `guarded` block again, also if the closure panics
+struct RestoreFlags(i32);
+
+impl Drop for RestoreFlags {
+    fn drop(&mut self) {
+        if self.0 != 0 {
+            unsafe {
+                feraiseexcept(self.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr::read_volatile;
+    use {AsRaw, Dirty};
+
+    // hides the value from the optimizer, so the operations happen at runtime
+    fn opaque(f: f64) -> f64 {
+        unsafe { read_volatile(&f) }
+    }
+
+    #[test]
+    fn clean_block() {
+        let r = guarded(|| (Dirty::new(opaque(1.0)) / Dirty::new(opaque(3.0))).as_raw());
+        assert!(r.is_ok());
+    }
+
+    #[test]
+    fn exceptions() {
+        let zero = Dirty::new(opaque(0.0));
+        let err = guarded(|| (zero / zero).as_raw()).unwrap_err();
+        assert!(err.invalid && !err.div_by_zero);
+        assert_eq!(format!("{}", err), "Floating point exception: invalid operation");
+
+        let err = guarded(|| (Dirty::new(opaque(1.0)) / zero).as_raw()).unwrap_err();
+        assert_eq!(err, FpExceptions { div_by_zero: true, ..Default::default() });
+
+        let big = Dirty::new(opaque(::std::f64::MAX));
+        let err = guarded(|| (big * big).as_raw()).unwrap_err();
+        assert!(err.overflow);
+    }
+
+    #[test]
+    fn nested() {
+        let zero = opaque(0.0);
+        let outer = guarded(|| {
+            let inner = guarded(|| zero / zero);
+            assert!(inner.is_err());
+            opaque(1.0) + opaque(1.0)
+        });
+        assert!(outer.unwrap_err().invalid);
+    }
+
+    #[test]
+    fn panicking_block() {
+        let zero = opaque(0.0);
+        let outer = guarded(|| {
+            opaque(zero / zero);
+            let inner = ::std::panic::catch_unwind(|| guarded(|| -> f64 { panic!("inner") }));
+            assert!(inner.is_err());
+            opaque(1.0)
+        });
+        assert!(outer.unwrap_err().invalid);
+    }
+}
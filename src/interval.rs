@@ -0,0 +1,581 @@
+// Copyright 2017 Matthias Tellen
+//
+// Permission is hereby granted,  free of charge,  to any person  obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without  limitation  the  rights to use,  copy,  modify,  merge,  publish,  distribute,
+// sublicense,  and/or sell copies of the Software,  and to permit  persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The  above  copyright  notice and this permission notice shall be included  in all copies or sub-
+// stantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS",  WITHOUT WARRANTY OF ANY KIND,  EXPRESS OR IMPLIED,  INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,  FITNESS  FOR A PARTICULAR PURPOSE AND NON-
+// INFRINGEMENT.  IN NO EVENT SHALL THE AUTHORS  OR  COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAM-
+// AGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,  TORT OR OTHERWISE, ARISING FROM, OUT
+// OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Interval arithmetic with outward rounding.
+//!
+//! An `Interval` is a closed range of extended reals. The lower bound is never positive infinity
+//! and the upper bound never negative infinity, which keeps every operation on the bounds free
+//! of NaN. Every bound that comes out of an operation is moved one float outwards, so the exact
+//! result is always enclosed. For the elementary functions this assumes that the underlying
+//! implementation is accurate to within one ulp.
+
+use core::cmp::Ordering;
+use core::ops::{Add, Mul, Neg, Sub};
+use num_traits::float::Float;
+use super::{AsRaw, Clean, UncheckedConv};
+use ::approx::Ulp;
+use ::error::FloatError;
+use ::nanpack::NanPack;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Interval<F: Float> {
+    lo: Clean<F>,
+    hi: Clean<F>,
+}
+
+// rounding helpers for the bounds. Results that are exact by construction are not moved.
+#[inline]
+fn down<F: Float + Ulp>(r: F, exact: bool) -> F {
+    if exact { r } else { r.next_down() }
+}
+
+#[inline]
+fn up<F: Float + Ulp>(r: F, exact: bool) -> F {
+    if exact { r } else { r.next_up() }
+}
+
+#[inline]
+fn is_exact_sum<F: Float>(a: F, b: F) -> bool {
+    a.is_zero() || b.is_zero() || a.is_infinite() || b.is_infinite()
+}
+
+// multiplication, where zero times infinity is zero
+#[inline]
+fn mul_down<F: Float + Ulp>(a: F, b: F) -> F {
+    if a.is_zero() || b.is_zero() {
+        return F::zero();
+    }
+    down(a * b, a.is_infinite() || b.is_infinite())
+}
+
+#[inline]
+fn mul_up<F: Float + Ulp>(a: F, b: F) -> F {
+    if a.is_zero() || b.is_zero() {
+        return F::zero();
+    }
+    up(a * b, a.is_infinite() || b.is_infinite())
+}
+
+// power of a non-negative value by squaring, every step rounded in the same direction
+fn pow_round<F: Float + Ulp>(x: F, mut n: u32, mul: fn(F, F) -> F) -> F {
+    let mut base = x;
+    let mut acc = F::one();
+    while n > 0 {
+        if n & 1 == 1 {
+            acc = mul(acc, base);
+        }
+        n >>= 1;
+        if n > 0 {
+            base = mul(base, base);
+        }
+    }
+    acc
+}
+
+fn min_of<F: Float>(xs: &[F]) -> F {
+    xs.iter().fold(F::infinity(), |acc, &x| acc.min(x))
+}
+
+fn max_of<F: Float>(xs: &[F]) -> F {
+    xs.iter().fold(F::neg_infinity(), |acc, &x| acc.max(x))
+}
+
+fn constant<F: Float>(f: f64) -> F {
+    F::from(f).unwrap_or_else(F::zero)
+}
+
+impl<F> Interval<F>
+where
+    F: Float + NanPack<usize> + Ulp,
+{
+    /// Creates the interval `[lo, hi]`. Fails if `lo > hi`, if `lo` is positive infinity or `hi`
+    /// negative infinity.
+    #[track_caller]
+    pub fn new(lo: Clean<F>, hi: Clean<F>) -> Result<Self, FloatError> {
+        let (l, h) = (lo.as_raw(), hi.as_raw());
+        if l > h || l == F::infinity() || h == F::neg_infinity() {
+            return Err(FloatError::interval_bounds(l, h));
+        }
+        Ok(Interval { lo, hi })
+    }
+
+    /// The interval that contains only `x`, or the widest interval towards the infinity if `x` is
+    /// infinite.
+    pub fn point(x: Clean<F>) -> Self {
+        let f = x.as_raw();
+        if f == F::infinity() {
+            Self::from_raw(F::max_value(), f)
+        } else if f == F::neg_infinity() {
+            Self::from_raw(f, F::min_value())
+        } else {
+            Self::from_raw(f, f)
+        }
+    }
+
+    /// The interval of all extended reals.
+    pub fn entire() -> Self {
+        Self::from_raw(F::neg_infinity(), F::infinity())
+    }
+
+    #[inline]
+    fn from_raw(lo: F, hi: F) -> Self {
+        Interval {
+            lo: Clean::from_raw(lo),
+            hi: Clean::from_raw(hi),
+        }
+    }
+
+    #[inline]
+    pub fn lo(&self) -> Clean<F> {
+        self.lo
+    }
+
+    #[inline]
+    pub fn hi(&self) -> Clean<F> {
+        self.hi
+    }
+
+    /// An upper bound of `hi - lo`.
+    #[inline]
+    pub fn width(&self) -> Clean<F> {
+        let (l, h) = (self.lo.as_raw(), self.hi.as_raw());
+        if l == h {
+            return Clean::from_raw(F::zero());
+        }
+        Clean::from_raw(up(h - l, is_exact_sum(h, l)))
+    }
+
+    #[inline]
+    pub fn contains<O: AsRaw<F> + Copy>(&self, x: O) -> bool {
+        self.lo.as_raw() <= x.as_raw() && x.as_raw() <= self.hi.as_raw()
+    }
+
+    /// Divides by `other`. If `other` contains zero, the result is the smallest interval that
+    /// encloses every quotient, which is often `entire()`. Dividing by `[0, 0]` fails.
+    #[track_caller]
+    pub fn checked_div(self, other: Self) -> Result<Self, FloatError> {
+        let (a, b) = (self.lo.as_raw(), self.hi.as_raw());
+        let (c, d) = (other.lo.as_raw(), other.hi.as_raw());
+        let zero = F::zero();
+        if c.is_zero() && d.is_zero() {
+            return Err(FloatError::interval_domain("Division"));
+        }
+        if c < zero && d > zero || a < zero && b > zero && c <= zero && zero <= d {
+            return Ok(Self::entire());
+        }
+        if c.is_zero() || d.is_zero() {
+            // the divisor touches zero from one side, so one bound goes to infinity
+            let positive_divisor = d > zero;
+            let (lo, hi) = match (a >= zero, positive_divisor) {
+                (true, true) => (down(a / d, a.is_zero()), F::infinity()),
+                (true, false) => (F::neg_infinity(), up(a / c, a.is_zero())),
+                (false, true) => (F::neg_infinity(), up(b / d, b.is_zero())),
+                (false, false) => (down(b / c, b.is_zero()), F::infinity()),
+            };
+            if b.is_zero() && a.is_zero() {
+                return Ok(Self::from_raw(zero, zero));
+            }
+            return Ok(Self::from_raw(lo, hi));
+        }
+        // zero isn't in the divisor, so the extremes lie on the corners. Infinity divided by
+        // infinity can be dropped, since a neighbouring corner is zero or infinite as well.
+        let mut quotients = [F::zero(); 4];
+        let mut n = 0;
+        for &(x, y) in &[(a, c), (a, d), (b, c), (b, d)] {
+            if !(x.is_infinite() && y.is_infinite()) {
+                quotients[n] = x / y;
+                n += 1;
+            }
+        }
+        let exact = |q: F| q.is_zero() || q.is_infinite();
+        let lo = min_of(&quotients[..n]);
+        let hi = max_of(&quotients[..n]);
+        Ok(Self::from_raw(down(lo, exact(lo)), up(hi, exact(hi))))
+    }
+
+    pub fn abs(self) -> Self {
+        let (l, h) = (self.lo.as_raw(), self.hi.as_raw());
+        if l >= F::zero() {
+            self
+        } else if h <= F::zero() {
+            -self
+        } else {
+            Self::from_raw(F::zero(), (-l).max(h))
+        }
+    }
+
+    #[track_caller]
+    pub fn powi(self, n: i32) -> Result<Self, FloatError> {
+        if n < 0 {
+            let one = Self::from_raw(F::one(), F::one());
+            return one.checked_div(self.pow_unsigned(n.wrapping_neg() as u32));
+        }
+        Ok(self.pow_unsigned(n as u32))
+    }
+
+    fn pow_unsigned(self, n: u32) -> Self {
+        if n == 0 {
+            return Self::from_raw(F::one(), F::one());
+        }
+        if n.is_multiple_of(2) {
+            let abs = self.abs();
+            return Self::from_raw(
+                pow_round(abs.lo.as_raw(), n, mul_down),
+                pow_round(abs.hi.as_raw(), n, mul_up),
+            );
+        }
+        // odd powers are monotone
+        let odd_pow = |x: F, lower: bool| if x < F::zero() {
+            -pow_round(-x, n, if lower { mul_up } else { mul_down })
+        } else {
+            pow_round(x, n, if lower { mul_down } else { mul_up })
+        };
+        Self::from_raw(odd_pow(self.lo.as_raw(), true), odd_pow(self.hi.as_raw(), false))
+    }
+
+    // Applies a function that is increasing on `[domain_lo, domain_hi]`, the interval is clipped
+    // to the domain first. The result is clipped to `[range_lo, range_hi]`.
+    #[track_caller]
+    fn increasing<C: Fn(F) -> F>(
+        self,
+        op: &'static str,
+        c: C,
+        domain: (F, F),
+        range: (F, F),
+    ) -> Result<Self, FloatError> {
+        let (l, h) = (self.lo.as_raw(), self.hi.as_raw());
+        if h < domain.0 || l > domain.1 {
+            return Err(FloatError::interval_domain(op));
+        }
+        let lo = c(l.max(domain.0)).next_down().max(range.0);
+        let hi = c(h.min(domain.1)).next_up().min(range.1);
+        Ok(Self::from_raw(lo, hi))
+    }
+
+    #[track_caller]
+    fn decreasing<C: Fn(F) -> F>(
+        self,
+        op: &'static str,
+        c: C,
+        domain: (F, F),
+        range: (F, F),
+    ) -> Result<Self, FloatError> {
+        (-self).increasing(op, |x| c(-x), (-domain.1, -domain.0), range)
+    }
+
+    // the full range of the extended reals
+    fn all() -> (F, F) {
+        (F::neg_infinity(), F::infinity())
+    }
+
+    #[track_caller]
+    pub fn sqrt(self) -> Result<Self, FloatError> {
+        let positive = (F::zero(), F::infinity());
+        self.increasing("Square root", Float::sqrt, positive, positive)
+    }
+
+    pub fn cbrt(self) -> Self {
+        self.total(Float::cbrt, Self::all())
+    }
+
+    pub fn exp(self) -> Self {
+        self.total(Float::exp, (F::zero(), F::infinity()))
+    }
+
+    pub fn exp2(self) -> Self {
+        self.total(Float::exp2, (F::zero(), F::infinity()))
+    }
+
+    pub fn exp_m1(self) -> Self {
+        self.total(Float::exp_m1, (-F::one(), F::infinity()))
+    }
+
+    #[track_caller]
+    pub fn ln(self) -> Result<Self, FloatError> {
+        self.increasing("Logarithm", Float::ln, (F::zero(), F::infinity()), Self::all())
+    }
+
+    #[track_caller]
+    pub fn log2(self) -> Result<Self, FloatError> {
+        self.increasing("Logarithm", Float::log2, (F::zero(), F::infinity()), Self::all())
+    }
+
+    #[track_caller]
+    pub fn log10(self) -> Result<Self, FloatError> {
+        self.increasing("Logarithm", Float::log10, (F::zero(), F::infinity()), Self::all())
+    }
+
+    #[track_caller]
+    pub fn ln_1p(self) -> Result<Self, FloatError> {
+        self.increasing("Logarithm", Float::ln_1p, (-F::one(), F::infinity()), Self::all())
+    }
+
+    pub fn atan(self) -> Self {
+        let half_pi = constant::<F>(::core::f64::consts::FRAC_PI_2).next_up();
+        self.total(Float::atan, (-half_pi, half_pi))
+    }
+
+    pub fn sinh(self) -> Self {
+        self.total(Float::sinh, Self::all())
+    }
+
+    pub fn tanh(self) -> Self {
+        self.total(Float::tanh, (-F::one(), F::one()))
+    }
+
+    pub fn asinh(self) -> Self {
+        self.total(Float::asinh, Self::all())
+    }
+
+    pub fn cosh(self) -> Self {
+        let abs = self.abs();
+        abs.total(Float::cosh, (F::one(), F::infinity()))
+    }
+
+    #[track_caller]
+    pub fn asin(self) -> Result<Self, FloatError> {
+        let half_pi = constant::<F>(::core::f64::consts::FRAC_PI_2).next_up();
+        self.increasing("Arcsine", Float::asin, (-F::one(), F::one()), (-half_pi, half_pi))
+    }
+
+    #[track_caller]
+    pub fn acos(self) -> Result<Self, FloatError> {
+        let pi = constant::<F>(::core::f64::consts::PI).next_up();
+        self.decreasing("Arccosine", Float::acos, (-F::one(), F::one()), (F::zero(), pi))
+    }
+
+    fn total<C: Fn(F) -> F>(self, c: C, range: (F, F)) -> Self {
+        match self.increasing("", c, Self::all(), range) {
+            Ok(i) => i,
+            Err(_) => unreachable!("every interval is in the domain"),
+        }
+    }
+
+    pub fn sin(self) -> Self {
+        let half_pi = constant::<F>(::core::f64::consts::FRAC_PI_2);
+        self.periodic(Float::sin, half_pi)
+    }
+
+    pub fn cos(self) -> Self {
+        self.periodic(Float::cos, F::zero())
+    }
+
+    // sine and cosine, which have their maximum at `max_at + 2kπ` and their minimum half a
+    // period later. An extremum is taken to be inside the interval if it is close to the
+    // bounds, since π isn't exact.
+    fn periodic<C: Fn(F) -> F>(self, c: C, max_at: F) -> Self {
+        let (l, h) = (self.lo.as_raw(), self.hi.as_raw());
+        let pi = constant::<F>(::core::f64::consts::PI);
+        let two_pi = pi + pi;
+        let one = F::one();
+        if h - l >= two_pi {
+            return Self::from_raw(-one, one);
+        }
+        let slop = (l.abs() + h.abs() + one) * F::epsilon() * constant(4.0);
+        let contains_extremum = |at: F| {
+            let k = ((l - at) / two_pi).ceil() - one;
+            let mut x = at + k * two_pi;
+            while x < l - slop {
+                x = x + two_pi;
+            }
+            x <= h + slop
+        };
+        let (cl, ch) = (c(l), c(h));
+        let hi = if contains_extremum(max_at) { one } else { cl.max(ch).next_up().min(one) };
+        let lo = if contains_extremum(max_at + pi) {
+            -one
+        } else {
+            cl.min(ch).next_down().max(-one)
+        };
+        Self::from_raw(lo, hi)
+    }
+}
+
+impl<F> From<Clean<F>> for Interval<F>
+where
+    F: Float + NanPack<usize> + Ulp,
+{
+    fn from(x: Clean<F>) -> Self {
+        Interval::point(x)
+    }
+}
+
+impl<F> Add for Interval<F>
+where
+    F: Float + NanPack<usize> + Ulp,
+{
+    type Output = Interval<F>;
+
+    fn add(self, other: Self) -> Self::Output {
+        let (a, b) = (self.lo.as_raw(), self.hi.as_raw());
+        let (c, d) = (other.lo.as_raw(), other.hi.as_raw());
+        Interval::from_raw(down(a + c, is_exact_sum(a, c)), up(b + d, is_exact_sum(b, d)))
+    }
+}
+
+impl<F> Sub for Interval<F>
+where
+    F: Float + NanPack<usize> + Ulp,
+{
+    type Output = Interval<F>;
+
+    fn sub(self, other: Self) -> Self::Output {
+        self + -other
+    }
+}
+
+impl<F> Mul for Interval<F>
+where
+    F: Float + NanPack<usize> + Ulp,
+{
+    type Output = Interval<F>;
+
+    fn mul(self, other: Self) -> Self::Output {
+        let (a, b) = (self.lo.as_raw(), self.hi.as_raw());
+        let (c, d) = (other.lo.as_raw(), other.hi.as_raw());
+        let lows = [mul_down(a, c), mul_down(a, d), mul_down(b, c), mul_down(b, d)];
+        let highs = [mul_up(a, c), mul_up(a, d), mul_up(b, c), mul_up(b, d)];
+        Interval::from_raw(min_of(&lows), max_of(&highs))
+    }
+}
+
+impl<F> Neg for Interval<F>
+where
+    F: Float + NanPack<usize> + Ulp,
+{
+    type Output = Interval<F>;
+
+    fn neg(self) -> Self::Output {
+        Interval::from_raw(-self.hi.as_raw(), -self.lo.as_raw())
+    }
+}
+
+impl<F> PartialOrd for Interval<F>
+where
+    F: Float + NanPack<usize> + Ulp,
+{
+    /// Intervals are ordered if they don't overlap. Identical intervals compare equal, consistent
+    /// with `==`.
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self == other {
+            Some(Ordering::Equal)
+        } else if self.hi < other.lo {
+            Some(Ordering::Less)
+        } else if self.lo > other.hi {
+            Some(Ordering::Greater)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use F64;
+
+    fn iv(lo: f64, hi: f64) -> Interval<f64> {
+        Interval::new(F64::try_new(lo).unwrap(), F64::try_new(hi).unwrap()).unwrap()
+    }
+
+    fn encloses(i: Interval<f64>, lo: f64, hi: f64) -> bool {
+        i.lo() <= lo && i.hi() >= hi
+    }
+
+    #[test]
+    fn new() {
+        use std::f64::INFINITY as INF;
+
+        assert!(Interval::new(F64::try_new(2.0).unwrap(), F64::try_new(1.0).unwrap()).is_err());
+        assert!(Interval::new(F64::try_new(INF).unwrap(), F64::try_new(INF).unwrap()).is_err());
+        assert!(Interval::new(F64::try_new(-INF).unwrap(), F64::try_new(INF).unwrap()).is_ok());
+        assert_eq!(Interval::point(F64::try_new(INF).unwrap()).hi(), INF);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = iv(0.1, 0.2);
+        let b = iv(-1.0, 3.0);
+        assert!(encloses(a + b, 0.1 - 1.0, 0.2 + 3.0));
+        assert!(encloses(a - b, 0.1 - 3.0, 0.2 + 1.0));
+        assert!(encloses(a * b, -0.2, 0.6));
+        assert!(encloses((a + a + a).checked_div(iv(3.0, 3.0)).unwrap(), 0.1, 0.2));
+        assert!((iv(1.0, 2.0) * iv(0.0, ::std::f64::INFINITY)).lo() == 0.0);
+        assert!(iv(0.0, 1.0).width() >= 1.0);
+    }
+
+    #[test]
+    fn ordering() {
+        assert_eq!(iv(0.0, 1.0).partial_cmp(&iv(0.0, 1.0)), Some(Ordering::Equal));
+        assert_eq!(iv(2.0, 2.0).partial_cmp(&iv(2.0, 2.0)), Some(Ordering::Equal));
+        assert!(iv(0.0, 1.0) < iv(2.0, 3.0));
+        assert!(iv(2.0, 3.0) > iv(0.0, 1.0));
+        assert_eq!(iv(0.0, 2.0).partial_cmp(&iv(1.0, 3.0)), None);
+        assert_eq!(iv(0.0, 1.0).partial_cmp(&iv(1.0, 2.0)), None);
+    }
+
+    #[test]
+    fn division_by_zero() {
+        use std::f64::INFINITY as INF;
+
+        assert!(iv(1.0, 2.0).checked_div(iv(0.0, 0.0)).is_err());
+        assert_eq!(iv(1.0, 2.0).checked_div(iv(-1.0, 1.0)).unwrap(), Interval::entire());
+        let i = iv(1.0, 2.0).checked_div(iv(0.0, 4.0)).unwrap();
+        assert!(i.lo() <= 0.25 && i.hi() == INF);
+        let i = iv(1.0, 2.0).checked_div(iv(-4.0, 0.0)).unwrap();
+        assert!(i.lo() == -INF && i.hi() >= -0.25);
+        assert_eq!(iv(0.0, 0.0).checked_div(iv(0.0, 1.0)).unwrap(), iv(0.0, 0.0));
+        let i = iv(1.0, INF).checked_div(iv(1.0, INF)).unwrap();
+        assert!(i.lo() <= 0.0 && i.hi() == INF);
+    }
+
+    #[test]
+    fn functions() {
+        use std::f64::consts::PI;
+
+        assert!(iv(-2.0, -1.0).sqrt().is_err());
+        assert!(encloses(iv(-1.0, 4.0).sqrt().unwrap(), 0.0, 2.0));
+        assert!(iv(-1.0, 4.0).sqrt().unwrap().lo() == 0.0);
+        assert!(iv(-2.0, -1.0).ln().is_err());
+        assert!(encloses(iv(1.0, 2.0).exp(), 1f64.exp(), 2f64.exp()));
+        assert!(encloses(iv(-2.0, 1.0).powi(2).unwrap(), 0.0, 4.0));
+        assert!(iv(-2.0, 1.0).powi(2).unwrap().lo() == 0.0);
+        assert!(encloses(iv(-2.0, 1.0).powi(3).unwrap(), -8.0, 1.0));
+        assert_eq!(iv(-2.0, 1.0).powi(-1).unwrap(), Interval::entire());
+        assert!(encloses(iv(2.0, 4.0).powi(-2).unwrap(), 1.0 / 16.0, 0.25));
+        assert!(encloses(iv(0.5, 1.0).acos().unwrap(), 0.0, PI / 3.0));
+        assert!(iv(2.0, 3.0).asin().is_err());
+        assert!(encloses(iv(-1.0, 1.0).cosh(), 1.0, 1f64.cosh()));
+    }
+
+    #[test]
+    fn trigonometry() {
+        use std::f64::consts::PI;
+
+        let i = iv(0.0, PI);
+        assert!(encloses(i.sin(), 0.0, 1.0));
+        assert_eq!(i.sin().hi(), 1.0);
+        assert!(encloses(i.cos(), -1.0, 1.0));
+        let i = iv(0.1, 0.2);
+        assert!(encloses(i.sin(), 0.1f64.sin(), 0.2f64.sin()));
+        assert!(i.sin().hi() < 1.0);
+        assert!(encloses(i.cos(), 0.2f64.cos(), 0.1f64.cos()));
+        assert_eq!(iv(0.0, 100.0).sin(), iv(-1.0, 1.0));
+        let i = iv(3.0 * PI / 2.0 - 0.1, 3.0 * PI / 2.0 + 0.1);
+        assert_eq!(i.sin().lo(), -1.0);
+    }
+}
@@ -20,23 +20,153 @@
 //! does the correct thing and doesn't implement Ord.
 //!
 //! ...
+//!
+//! The crate works without `std` when the default `std` feature is disabled. Transcendental
+//! functions then come from `libm`. The error buffer that lets a NaN remember how it came to be
+//! only needs the `alloc` feature, but errors then don't know the location they happened at.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
+#[cfg(any(feature = "std", test))]
+extern crate core;
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate lazy_static;
+#[cfg(feature = "std")]
 extern crate backtrace;
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+extern crate spin;
 extern crate num_traits;
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+#[cfg(feature = "rand")]
+extern crate rand;
 
 mod error;
 mod trait_impls;
 mod nanpack;
+mod conv;
+mod checked;
+mod option;
+#[macro_use]
+mod approx;
+mod interval;
+mod complex;
+mod dual;
+mod angle;
+mod vector;
+mod slice;
+#[cfg(feature = "std")]
+mod batch;
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod fenv;
+#[cfg(feature = "serde")]
+mod serde_impls;
+#[cfg(feature = "rand")]
+mod rand_impls;
 
 use num_traits::float::Float;
+#[cfg(feature = "std")]
 pub use failure::Error;
-use error::{FloatError, FLOAT_ERROR_BUFFER};
-use nanpack::NanPack;
+#[cfg(feature = "alloc")]
+pub use error::{set_payload_tag_bits, set_range_warnings};
+use error::FloatError;
+#[cfg(all(feature = "alloc", not(build = "release")))]
+use error::FLOAT_ERROR_BUFFER;
+pub use nanpack::{NanMeta, NanPack};
+pub use conv::Rounding;
+pub use option::OptionClean;
+pub use approx::Ulp;
+pub use interval::Interval;
+pub use complex::{CleanComplex, ComplexParts, DirtyComplex};
+pub use dual::Dual;
+pub use angle::{Degrees, Radians};
+pub use vector::{DirtyVec2, DirtyVec3, DirtyVec4, Vec2, Vec3, Vec4};
+#[cfg(feature = "rand")]
+pub use rand_impls::{Exp, LogNormal, Normal, Uniform, UnitInterval};
+pub use slice::{clean_as_dirty, clean_as_raw, dirty_as_raw, dirty_as_raw_mut, raw_as_dirty,
+                raw_as_dirty_mut};
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub use fenv::{guarded, FpExceptions};
+#[cfg(feature = "std")]
+pub use batch::{batch, Batch, BatchFloatError, BatchSanitizer, SanitizeAll};
+#[cfg(feature = "alloc")]
+pub use slice::{sanitize_slice, sanitize_slice_mut, sanitize_vec, SliceFloatError};
+
+/// Creates a `Clean` from a constant expression. A NaN is rejected at compile time, so this can
+/// be used to initialize `const` and `static` items.
+///
+/// The expression is checked at the type it's stored as, which is `f64` unless it's prefixed
+/// with `f32:`. An expression that is fine as a `f64` can still overflow as a `f32`.
+///
+/// ```
+/// #[macro_use]
+/// extern crate clean_float;
+/// use clean_float::{F32, F64};
+///
+/// const HALF: F64 = clean!(0.5);
+/// const QUARTER: F32 = clean!(f32: 0.25);
+/// # fn main() { assert_eq!(HALF, 0.5); assert_eq!(QUARTER, 0.25); }
+/// ```
+///
+/// ```compile_fail
+/// #[macro_use]
+/// extern crate clean_float;
+/// use clean_float::F64;
+///
+/// const BAD: F64 = clean!(0.0 / 0.0);
+/// # fn main() {}
+/// ```
+#[macro_export]
+macro_rules! clean {
+    (f32: $f: expr) => { $crate::clean!(@f32, $f) };
+    (f64: $f: expr) => { $crate::clean!(@f64, $f) };
+    (@$t: ty, $f: expr) => {{
+        const VALUE: $t = $f;
+        const _CHECK: () = assert!(VALUE == VALUE, "clean! doesn't accept NaN");
+        unsafe { $crate::Clean::<$t>::new_unchecked(VALUE) }
+    }};
+    ($f: expr) => { $crate::clean!(@f64, $f) };
+}
+
+/// Like `clean!`, but rejects the infinities as well.
+///
+/// ```compile_fail
+/// #[macro_use]
+/// extern crate clean_float;
+/// use clean_float::F64;
+///
+/// const BAD: F64 = finite!(1.0 / 0.0);
+/// # fn main() {}
+/// ```
+///
+/// ```compile_fail
+/// #[macro_use]
+/// extern crate clean_float;
+/// use clean_float::F32;
+///
+/// // finite as a f64, but not as a f32
+/// const BAD: F32 = finite!(f32: 3e38 * 10.0);
+/// # fn main() {}
+/// ```
+#[macro_export]
+macro_rules! finite {
+    (f32: $f: expr) => { $crate::finite!(@f32, $f) };
+    (f64: $f: expr) => { $crate::finite!(@f64, $f) };
+    (@$t: ty, $f: expr) => {{
+        const VALUE: $t = $f;
+        const _CHECK: () = assert!(VALUE - VALUE == 0.0, "finite! doesn't accept NaN or infinity");
+        unsafe { $crate::Clean::<$t>::new_unchecked(VALUE) }
+    }};
+    ($f: expr) => { $crate::finite!(@f64, $f) };
+}
 
 pub type F64 = Clean<f64>;
 pub type DirtyF64 = Dirty<f64>;
@@ -44,20 +174,43 @@ pub type DirtyF64 = Dirty<f64>;
 pub type F32 = Clean<f32>;
 pub type DirtyF32 = Dirty<f32>;
 
-pub trait UncheckedConv<F>
+/// Read access to the raw float. Implemented by `Clean`, `Dirty` and the primitive floats, so
+/// any of them can be an operand.
+pub trait AsRaw<F>
 where
     Self: Sized,
 {
     fn as_raw(self) -> F;
+}
+
+mod sealed {
+    /// Can only be created inside this crate, so the methods that take one can't be called
+    /// from anywhere else, not even through a generic bound.
+    pub struct Token(());
+
+    pub(crate) const TOKEN: Token = Token(());
+
+    pub trait Sealed<F> {
+        fn from_raw_sealed(f: F, token: Token) -> Self;
+    }
+}
 
+// Construction without checks. Only usable inside the crate, since a `Clean` built from a NaN
+// would break the invariant everything else relies on. Use `Clean::new_unchecked` from outside.
+pub(crate) trait UncheckedConv<F>: AsRaw<F> + sealed::Sealed<F> {
     #[inline]
     fn map<C: Fn(F) -> F>(self, c: C) -> Self {
-        UncheckedConv::from_raw(c(self.as_raw()))
+        Self::from_raw(c(self.as_raw()))
     }
 
-    fn from_raw(F) -> Self;
+    #[inline]
+    fn from_raw(f: F) -> Self {
+        Self::from_raw_sealed(f, sealed::TOKEN)
+    }
 }
 
+impl<F, T: AsRaw<F> + sealed::Sealed<F>> UncheckedConv<F> for T {}
+
 // some macro helpers to replicate all the methods for CleanFloat from Float
 macro_rules! non_tainting_method {
     ($method_name: ident) => {
@@ -68,7 +221,7 @@ macro_rules! non_tainting_method {
     };
     ($method_name: ident, rhs) => {
         #[inline]
-            fn $method_name<O: UncheckedConv<F> + Copy>(self, o: O) -> Self {
+            fn $method_name<O: AsRaw<F> + Copy>(self, o: O) -> Self {
                 self.map(|x| Float::$method_name(x, o.as_raw()))
             }
     }
@@ -86,7 +239,7 @@ macro_rules! tainting_method {
 pub trait CleanFloat<F>
 where
     F: Float + NanPack<usize>,
-    Self: Sized + UncheckedConv<F>,
+    Self: Sized + AsRaw<F> + sealed::Sealed<F>,
 {
     non_tainting_method!(floor);
     non_tainting_method!(ceil);
@@ -111,7 +264,7 @@ where
     }
 
     #[inline]
-    fn mul_add<A: UncheckedConv<F> + Copy, B: UncheckedConv<F> + Copy>(
+    fn mul_add<A: AsRaw<F> + Copy, B: AsRaw<F> + Copy>(
         self,
         a: A,
         b: B,
@@ -124,7 +277,7 @@ where
         Dirty::new(self.as_raw().powi(exp))
     }
     #[inline]
-    fn powf<A: UncheckedConv<F> + Copy>(self, exp: A) -> Dirty<F> {
+    fn powf<A: AsRaw<F> + Copy>(self, exp: A) -> Dirty<F> {
         Dirty::new(self.as_raw().powf(exp.as_raw()))
     }
 
@@ -134,7 +287,7 @@ where
     tainting_method!(ln);
 
     #[inline]
-    fn log<A: UncheckedConv<F> + Copy>(self, a: A) -> Dirty<F> {
+    fn log<A: AsRaw<F> + Copy>(self, a: A) -> Dirty<F> {
         Dirty::new(self.as_raw().log(a.as_raw()))
     }
 
@@ -155,25 +308,179 @@ where
     fn taint(self) -> Dirty<F> {
         Dirty::<F>::new(self.as_raw())
     }
+
+    /// Applies a user-defined function. If it returns NaN, an error that names `op` and the
+    /// location of the call is recorded, like for the built-in operations.
+    #[inline]
+    #[track_caller]
+    fn try_map<C: FnOnce(F) -> F>(self, op: &'static str, c: C) -> Dirty<F> {
+        let a = self.as_raw();
+        #[cfg(all(feature = "alloc", not(build = "release")))]
+        {
+            if a.is_payloaded() {
+                return Dirty::new(a);
+            }
+            let result = c(a);
+            if result.is_nan() && !a.is_nan() {
+                return Dirty::new(FLOAT_ERROR_BUFFER.insert(FloatError::function(op, a)));
+            }
+            Dirty::new(result)
+        }
+        #[cfg(not(all(feature = "alloc", not(build = "release"))))]
+        {
+            let _ = op;
+            Dirty::new(c(a))
+        }
+    }
+
+    /// Like `try_map`, but returns the error right away.
+    #[inline]
+    #[track_caller]
+    fn map_checked<C: FnOnce(F) -> F>(
+        self,
+        op: &'static str,
+        c: C,
+    ) -> Result<Clean<F>, FloatError> {
+        let a = self.as_raw();
+        if a.is_nan() {
+            return Clean::try_new(a);
+        }
+        let result = c(a);
+        if result.is_nan() {
+            return Err(FloatError::function(op, a));
+        }
+        Ok(Clean::from_raw(result))
+    }
+
+    #[inline]
+    fn next_up(self) -> Self
+    where
+        F: Ulp,
+    {
+        self.map(Ulp::next_up)
+    }
+
+    #[inline]
+    fn next_down(self) -> Self
+    where
+        F: Ulp,
+    {
+        self.map(Ulp::next_down)
+    }
+
+    /// The distance to the next float away from zero. Infinite for the infinities.
+    #[inline]
+    fn ulp(self) -> Self
+    where
+        F: Ulp,
+    {
+        self.map(|x| {
+            let x = x.abs();
+            if x.is_infinite() { x } else { x.next_up() - x }
+        })
+    }
+
+    /// True if at most `max_ulps` floats lie between `self` and `other`.
+    #[inline]
+    fn approx_eq_ulps<O: AsRaw<F> + Copy>(self, other: O, max_ulps: u64) -> bool
+    where
+        F: Ulp,
+    {
+        match self.as_raw().ulps_between(other.as_raw()) {
+            Some(ulps) => ulps <= max_ulps,
+            None => false,
+        }
+    }
+
+    /// True if the difference is at most `rel_tol` times the greater magnitude. Infinities only
+    /// compare equal to themselves.
+    #[inline]
+    fn approx_eq_rel<O: AsRaw<F> + Copy, T: AsRaw<F> + Copy>(
+        self,
+        other: O,
+        rel_tol: T,
+    ) -> bool {
+        let (a, b) = (self.as_raw(), other.as_raw());
+        if a == b {
+            return true;
+        }
+        if a.is_infinite() || b.is_infinite() {
+            return false;
+        }
+        (a - b).abs() <= rel_tol.as_raw() * a.abs().max(b.abs())
+    }
+
+    /// True if the difference is at most `abs_tol`.
+    #[inline]
+    fn approx_eq_abs<O: AsRaw<F> + Copy, T: AsRaw<F> + Copy>(
+        self,
+        other: O,
+        abs_tol: T,
+    ) -> bool {
+        let (a, b) = (self.as_raw(), other.as_raw());
+        a == b || (a - b).abs() <= abs_tol.as_raw()
+    }
 }
 
+/// A float that is guaranteed not to be NaN.
+///
+/// The only safe ways to create one check the value, e.g. `try_new`, `sanitize` or `clean!`.
+/// None of the following compile:
+///
+/// ```compile_fail
+/// # extern crate clean_float;
+/// # fn main() {
+/// let nan = clean_float::F64::from_raw(std::f64::NAN);
+/// # }
+/// ```
+///
+/// ```compile_fail
+/// # extern crate clean_float;
+/// # use clean_float::{CleanFloat, F64};
+/// // not even through a generic bound
+/// fn sneak<T: CleanFloat<f64>>(x: T) -> T {
+///     x.map(|_| std::f64::NAN)
+/// }
+/// # fn main() {}
+/// ```
+///
+/// ```compile_fail
+/// # extern crate clean_float;
+/// use clean_float::UncheckedConv;
+/// # fn main() {}
+/// ```
+///
+/// ```compile_fail
+/// # extern crate clean_float;
+/// # fn main() {
+/// let nan = clean_float::Clean(std::f64::NAN);
+/// # }
+/// ```
+///
+/// ```compile_fail
+/// # extern crate clean_float;
+/// # fn main() {
+/// // skipping the check has to be spelled out
+/// let nan = clean_float::F64::new_unchecked(std::f64::NAN);
+/// # }
+/// ```
 #[derive(Debug, Copy, Clone)]
+#[repr(transparent)]
 pub struct Clean<F: Float>(F);
 #[derive(Debug, Copy, Clone)]
+#[repr(transparent)]
 pub struct Dirty<F: Float>(F);
 
 
 impl<F> CleanFloat<F> for Clean<F>
 where
     F: Float + NanPack<usize>,
-    Clean<F>: UncheckedConv<F>,
 {
 }
 
 impl<F> CleanFloat<F> for Dirty<F>
 where
     F: Float + NanPack<usize>,
-    Dirty<F>: UncheckedConv<F>,
 {
 }
 
@@ -181,12 +488,30 @@ impl<F> Clean<F>
 where
     F: Float + NanPack<usize>,
 {
+    /// Creates a `Clean` without checking for NaN. Usable in constant expressions, see `clean!`
+    /// for a checked alternative.
+    ///
+    /// # Safety
+    ///
+    /// `f` must not be NaN.
+    #[inline]
+    pub const unsafe fn new_unchecked(f: F) -> Clean<F> {
+        Clean(f)
+    }
+
     #[inline]
     pub fn try_new(f: F) -> Result<Clean<F>, FloatError> {
         if f.is_nan() {
-            if cfg!(not(build = "release")) {
+            #[cfg(all(feature = "alloc", not(build = "release")))]
+            {
+                if f.is_exhausted() {
+                    return Err(FloatError::buffer_exhausted());
+                }
                 if let Some(errno) = f.get_payload() {
-                    return Err(FLOAT_ERROR_BUFFER.remove(errno));
+                    return match FLOAT_ERROR_BUFFER.remove::<F>(errno) {
+                        Some(err) => Err(err),
+                        None => Err(FloatError::expired()),
+                    };
                 }
             }
             return Err(FloatError::sanitization(f).into());
@@ -208,44 +533,179 @@ where
     pub fn sanitize(self) -> Result<Clean<F>, FloatError> {
         Clean::try_new(self.as_raw())
     }
+
+    /// Takes the overflow and underflow warnings that were recorded since the last call. The
+    /// warnings of `f32` and `f64` operations are collected together, so `F` doesn't matter.
+    /// Warnings are only recorded after `set_range_warnings(true)`. Unlike errors, they don't
+    /// change the result of an operation.
+    #[cfg(all(feature = "alloc", not(build = "release")))]
+    pub fn warnings() -> ::alloc::vec::Vec<FloatError> {
+        FLOAT_ERROR_BUFFER.take_warnings()
+    }
+
+    /// True if the value is not NaN. Doesn't consume a stored error.
+    #[inline]
+    pub fn is_clean(&self) -> bool {
+        !self.as_raw().is_nan()
+    }
+
+    /// Sanitizes, panicking with the error.
+    #[inline]
+    pub fn unwrap(self) -> Clean<F> {
+        self.sanitize().unwrap()
+    }
+
+    /// Sanitizes, panicking with `msg` and the error.
+    #[inline]
+    pub fn expect(self, msg: &str) -> Clean<F> {
+        match self.sanitize() {
+            Ok(c) => c,
+            Err(err) => panic!("{}: {}", msg, err),
+        }
+    }
+
+    /// Sanitizes, falling back to `default`. The error is discarded.
+    #[inline]
+    pub fn unwrap_or(self, default: Clean<F>) -> Clean<F> {
+        self.sanitize().unwrap_or(default)
+    }
+
+    #[inline]
+    pub fn unwrap_or_else<C: FnOnce(FloatError) -> Clean<F>>(self, c: C) -> Clean<F> {
+        self.sanitize().unwrap_or_else(c)
+    }
+
+    /// Sanitizes, falling back to zero.
+    #[inline]
+    pub fn unwrap_or_default(self) -> Clean<F> {
+        self.unwrap_or(Clean::from_raw(F::zero()))
+    }
+
+    /// Returns `self` if it isn't NaN and `other` otherwise. The error of `self` is discarded.
+    #[inline]
+    pub fn or(self, other: Dirty<F>) -> Dirty<F> {
+        match self.sanitize() {
+            Ok(_) => self,
+            Err(_) => other,
+        }
+    }
+
+    #[inline]
+    pub fn or_else<C: FnOnce(FloatError) -> Dirty<F>>(self, c: C) -> Dirty<F> {
+        match self.sanitize() {
+            Ok(_) => self,
+            Err(err) => c(err),
+        }
+    }
+
+    /// Replaces a NaN in place and returns its error. Useful to clean up a slice of `Dirty`s
+    /// while logging what went wrong.
+    #[inline]
+    pub fn replace_nan_with(&mut self, replacement: Clean<F>) -> Option<FloatError> {
+        match self.sanitize() {
+            Ok(_) => None,
+            Err(err) => {
+                *self = Dirty::from_raw(replacement.as_raw());
+                Some(err)
+            }
+        }
+    }
 }
 
-impl<F: Float> UncheckedConv<F> for Clean<F> {
+impl Clean<f64> {
+    /// Converts to a `Clean<f32>`. Fails if a finite value overflows to infinity or can't be
+    /// represented exactly.
     #[inline]
-    fn as_raw(self) -> F {
-        self.0
+    #[track_caller]
+    pub fn to_f32(self) -> Result<Clean<f32>, FloatError> {
+        let narrow = self.to_f32_rounded()?;
+        if narrow.as_raw() as f64 != self.as_raw() {
+            return Err(FloatError::narrowing_precision_loss(self.as_raw()));
+        }
+        Ok(narrow)
     }
 
+    /// Converts to a `Clean<f32>`, rounding to the nearest f32. Fails if a finite value overflows
+    /// to infinity.
     #[inline]
-    fn from_raw(f: F) -> Self {
-        Clean(f)
+    #[track_caller]
+    pub fn to_f32_rounded(self) -> Result<Clean<f32>, FloatError> {
+        let f = self.as_raw();
+        let narrow = f as f32;
+        if narrow.is_infinite() && f.is_finite() {
+            return Err(FloatError::narrowing_overflow(f));
+        }
+        Ok(Clean::from_raw(narrow))
+    }
+}
+
+impl Dirty<f64> {
+    /// Converts to a `Dirty<f32>`. NaNs that carry an error keep it.
+    #[inline]
+    pub fn to_f32(self) -> Dirty<f32> {
+        let f = self.as_raw();
+        #[cfg(all(feature = "alloc", not(build = "release")))]
+        {
+            if f.is_payloaded() {
+                return Dirty::from_raw(FLOAT_ERROR_BUFFER.remap(f));
+            }
+        }
+        Dirty::from_raw(f as f32)
     }
 }
 
-impl<F: Float> UncheckedConv<F> for Dirty<F> {
+impl<F: Float> AsRaw<F> for Clean<F> {
     #[inline]
     fn as_raw(self) -> F {
         self.0
     }
+}
 
+impl<F: Float> sealed::Sealed<F> for Clean<F> {
     #[inline]
-    fn from_raw(f: F) -> Self {
-        Dirty(f)
+    fn from_raw_sealed(f: F, _: sealed::Token) -> Self {
+        Clean(f)
     }
 }
 
-impl<F: Float> UncheckedConv<F> for F {
+impl<F: Float> AsRaw<F> for Dirty<F> {
     #[inline]
     fn as_raw(self) -> F {
-        self
+        self.0
     }
+}
 
+impl<F: Float> sealed::Sealed<F> for Dirty<F> {
     #[inline]
-    fn from_raw(f: F) -> Self {
-        f
+    fn from_raw_sealed(f: F, _: sealed::Token) -> Self {
+        Dirty(f)
+    }
+}
+
+// Implemented for the primitives only, since `Dirty` is a `Float` too and would get two
+// `AsRaw` impls otherwise.
+macro_rules! impl_unchecked_conv_primitive {
+    ( $( $f: ty ),* ) => {
+        $(
+            impl AsRaw<$f> for $f {
+                #[inline]
+                fn as_raw(self) -> $f {
+                    self
+                }
+            }
+
+            impl sealed::Sealed<$f> for $f {
+                #[inline]
+                fn from_raw_sealed(f: $f, _: sealed::Token) -> Self {
+                    f
+                }
+            }
+        )*
     }
 }
 
+impl_unchecked_conv_primitive!(f32, f64);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -269,4 +729,145 @@ mod tests {
         assert_eq!(a + 1.0, 2.0);
         assert_eq!((a + b).sanitize().unwrap(), c);
     }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn widening() {
+        let a = F32::try_new(1.5).unwrap();
+        let b: F64 = a.into();
+        assert_eq!(b, 1.5);
+
+        let zero = F32::try_new(0.0).unwrap();
+        let nan: Dirty<f64> = (zero / zero).into();
+        assert!(nan.as_raw().is_payloaded());
+        assert!(nan.sanitize().is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn narrowing() {
+        assert_eq!(F64::try_new(1.5).unwrap().to_f32().unwrap(), 1.5);
+        assert!(F64::try_new(0.1).unwrap().to_f32().is_err());
+        assert_eq!(F64::try_new(0.1).unwrap().to_f32_rounded().unwrap(), 0.1f32);
+        assert!(F64::try_new(1e300).unwrap().to_f32_rounded().is_err());
+        assert!(F64::try_new(::std::f64::INFINITY).unwrap().to_f32().is_ok());
+
+        let zero = F64::try_new(0.0).unwrap();
+        let nan = (zero / zero).to_f32();
+        assert!(nan.as_raw().is_payloaded());
+        assert!(nan.sanitize().is_err());
+        assert_eq!(DirtyF64::new(2.5).to_f32(), 2.5f32);
+    }
+
+    #[test]
+    fn user_functions() {
+        let half = F64::try_new(0.5).unwrap();
+        let logit = |p: f64| (p / (1.0 - p)).ln();
+
+        assert_eq!(half.try_map("logit", logit).sanitize().unwrap(), 0.0);
+        assert_eq!(half.map_checked("logit", logit).unwrap(), 0.0);
+        let two = F64::try_new(2.0).unwrap();
+        let err = two.map_checked("logit", logit).unwrap_err();
+        assert!(format!("{}", err).ends_with("logit of value resulted in NaN"));
+        let err = two.try_map("logit", logit).sanitize().unwrap_err();
+        #[cfg(feature = "alloc")]
+        assert!(format!("{}", err).ends_with("logit of value resulted in NaN"));
+        let _ = err;
+
+        // the errors point at the call, not into the crate
+        #[cfg(feature = "std")]
+        {
+            let (err, line) = (two.map_checked("logit", logit).unwrap_err(), line!());
+            assert!(format!("{}", err).starts_with(&format!("{}:{}:", file!(), line)));
+            let (nan, line) = (two.try_map("logit", logit), line!());
+            let err = nan.sanitize().unwrap_err();
+            assert!(format!("{}", err).starts_with(&format!("{}:{}:", file!(), line)));
+        }
+
+        // NaNs from earlier operations keep their error
+        let zero = F64::try_new(0.0).unwrap();
+        let err = (zero / zero).map_checked("logit", logit).unwrap_err();
+        #[cfg(feature = "alloc")]
+        assert!(format!("{}", err).ends_with("Division zero by zero resulted in NaN"));
+        let _ = err;
+    }
+
+    #[test]
+    fn combinators() {
+        let zero = F64::try_new(0.0).unwrap();
+        let one = F64::try_new(1.0).unwrap();
+        let good = DirtyF64::new(2.0);
+        let nan = zero / zero;
+
+        assert!(good.is_clean() && !nan.is_clean());
+        assert_eq!(good.unwrap(), 2.0);
+        assert_eq!(good.unwrap_or(one), 2.0);
+        assert_eq!(nan.unwrap_or(one), 1.0);
+        assert_eq!((zero / zero).unwrap_or_else(|_| one), 1.0);
+        assert_eq!((zero / zero).unwrap_or_default(), 0.0);
+        assert_eq!((zero / zero).or(good).as_raw(), 2.0);
+        assert_eq!(good.or(DirtyF64::new(3.0)).as_raw(), 2.0);
+        assert_eq!((zero / zero).or_else(|_| good).as_raw(), 2.0);
+        assert_eq!(good.expect("never fails"), 2.0);
+
+        let mut xs = [DirtyF64::new(1.0), zero / zero];
+        assert!(xs[0].replace_nan_with(zero).is_none());
+        assert!(xs[1].replace_nan_with(zero).is_some());
+        assert_eq!(xs[1].as_raw(), 0.0);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn combinators_consume_errors() {
+        let zero = F64::try_new(0.0).unwrap();
+        let nan = zero / zero;
+        let copy = nan;
+        assert_eq!(nan.unwrap_or(zero), 0.0);
+        // the entry was taken out of the buffer, a copy of the NaN only finds it expired
+        let err = copy.sanitize().unwrap_err();
+        assert!(format!("{}", err).ends_with("already retrieved or overwritten"));
+    }
+
+    #[test]
+    #[should_panic(expected = "ratio")]
+    fn expect() {
+        let zero = F64::try_new(0.0).unwrap();
+        (zero / zero).expect("ratio");
+    }
+
+    #[test]
+    fn num_traits() {
+        use num_traits::{Bounded, FromPrimitive, Num, NumCast, Zero};
+
+        fn sum<T: Num + Copy>(xs: &[T]) -> T {
+            xs.iter().fold(T::zero(), |acc, &x| acc + x)
+        }
+
+        fn norm<T: Float>(xs: &[T]) -> T {
+            xs.iter().fold(T::zero(), |acc, &x| acc + x * x).sqrt()
+        }
+
+        let xs = [DirtyF64::new(3.0), DirtyF64::new(4.0)];
+        assert_eq!(sum(&xs), 7.0);
+        assert_eq!(norm(&xs), 5.0);
+        assert_eq!(xs[0] - xs[1], -1.0);
+        assert!(DirtyF64::zero().is_zero());
+
+        assert_eq!(<F64 as Bounded>::max_value(), ::std::f64::MAX);
+        assert!(<F64 as FromPrimitive>::from_f64(::std::f64::NAN).is_none());
+        assert!(<DirtyF64 as FromPrimitive>::from_f64(::std::f64::NAN).is_some());
+        assert_eq!(<F32 as NumCast>::from(3u8).unwrap(), 3.0);
+    }
+
+    const ONE: F64 = clean!(1.0);
+    static TWO: F32 = finite!(f32: 2.0);
+
+    #[test]
+    fn literals() {
+        assert_eq!(ONE, 1.0);
+        assert_eq!(TWO, 2.0);
+        assert_eq!(clean!(::std::f64::INFINITY), ::std::f64::INFINITY);
+        let x: F32 = finite!(f32: -0.25);
+        assert_eq!(x, -0.25);
+    }
 }
@@ -1,48 +1,132 @@
 // We're using manual transmutes instead of the to- and from_bits() methods to preserve the sNaNs
 #![cfg_attr(feature = "cargo-clippy", allow(transmute_int_to_float))]
-use std::mem::transmute;
+use core::mem::transmute;
 
-const F32_PAYLOAD_MASK: u32 = 0x1F_FFFF;
+// The payload lives in the mantissa bits below the quiet bit. The all-ones payload is reserved
+// for the "exhausted" NaN that is handed out when an error index doesn't fit into the payload,
+// the one below that for the reserved NaN that marks the absence of a value in `OptionClean`.
+const F32_PAYLOAD_MASK: u32 = 0x3F_FFFF;
 const F32_EMPTY_NAN: u32 = 0x_7fc0_0000;
+const F32_SIGN_BIT: u32 = 0x8000_0000;
+const F32_QUIET_BIT: u32 = 0x40_0000;
 
-const F64_PAYLOAD_MASK: u64 = 0x3_FFFF_FFFF_FFFF;
+const F64_PAYLOAD_MASK: u64 = 0x7_FFFF_FFFF_FFFF;
 const F64_EMPTY_NAN: u64 = 0x7ff8_0000_0000_0000;
+const F64_SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+const F64_QUIET_BIT: u64 = 0x8_0000_0000_0000;
+
+/// The bits of a NaN that are not part of the payload. They can carry additional information
+/// about an error, e.g. that it came from narrowing a f64 to a f32.
+///
+/// The sign survives `Neg`, `abs`, `copysign` and casts between f32 and f64, since these only
+/// touch the sign bit or widen/narrow the mantissa. Arithmetic and casts turn signaling NaNs
+/// into quiet ones, so `signaling` only survives the bit-level operations.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct NanMeta {
+    pub negative: bool,
+    pub signaling: bool,
+}
 
 pub trait NanPack<T> {
-    fn set_payload(T) -> Self;
+    /// The number of distinct payloads that can be stored, i.e. `set_payload` accepts values in
+    /// `0..capacity()`.
+    fn capacity() -> T;
+    /// Packs `val` into a NaN. If `val` exceeds the capacity, the exhausted NaN is returned.
+    fn set_payload(val: T) -> Self;
+    /// A NaN that signals that an error occured, but its payload couldn't be stored.
+    fn exhausted() -> Self;
+    /// A NaN that is never produced by `set_payload`, used as a marker by `OptionClean`.
+    fn reserved() -> Self;
+    /// True for every NaN that was produced by `set_payload`, including the exhausted NaN.
     fn is_payloaded(self) -> bool;
+    fn is_exhausted(self) -> bool;
+    fn is_reserved(self) -> bool;
     fn get_payload(self) -> Option<T>;
+    /// Like `set_payload`, but also encodes the sign and quiet bit.
+    fn set_payload_with_meta(val: T, meta: NanMeta) -> Self;
+    /// Decodes the sign and quiet bit of a payloaded NaN.
+    fn get_meta(self) -> Option<NanMeta>;
 }
 
 // just a little macro to avoid repeating the same stuff for f64 and f32
 macro_rules! impl_NanPack {
-    ( $f: ty, $u: ty, $payload_mask: ident, $empty_nan: ident) => {
+    ( $f: ty, $u: ty, $payload_mask: ident, $empty_nan: ident, $sign_bit: ident,
+      $quiet_bit: ident) => {
         impl NanPack<usize> for $f {
+            fn capacity() -> usize {
+                // `val + 1` must stay below the reserved payload and the exhausted payload
+                ::core::cmp::min($payload_mask as u64 - 2, ::core::usize::MAX as u64) as usize
+            }
+
             fn set_payload(val: usize) -> Self {
+                if val >= Self::capacity() {
+                    return Self::exhausted();
+                }
                 let val = (val + 1) as $u;
-                assert!(val <= $payload_mask);
                 unsafe { transmute(val | $empty_nan) }
             }
 
+            fn exhausted() -> Self {
+                unsafe { transmute($payload_mask | $empty_nan) }
+            }
+
+            fn reserved() -> Self {
+                unsafe { transmute(($payload_mask - 1) | $empty_nan) }
+            }
+
             fn is_payloaded(self) -> bool {
                 let bits: $u = unsafe { transmute(self) };
-                self.is_nan() && ((bits & $payload_mask) > 0)
+                self.is_nan() && ((bits & $payload_mask) > 0) && !self.is_reserved()
+            }
+
+            fn is_exhausted(self) -> bool {
+                let bits: $u = unsafe { transmute(self) };
+                self.is_nan() && ((bits & $payload_mask) == $payload_mask)
+            }
+
+            fn is_reserved(self) -> bool {
+                let bits: $u = unsafe { transmute(self) };
+                self.is_nan() && ((bits & $payload_mask) == $payload_mask - 1)
             }
 
             fn get_payload(self) -> Option<usize> {
-                if !self.is_payloaded() {
+                if !self.is_payloaded() || self.is_exhausted() {
                     return None;
                 }
                 let bits: $u = self.to_bits();
                 let payload = bits & $payload_mask;
                 Some((payload - 1) as usize)
             }
+
+            fn set_payload_with_meta(val: usize, meta: NanMeta) -> Self {
+                let nan: Self = Self::set_payload(val);
+                let mut bits: $u = unsafe { transmute(nan) };
+                if meta.negative {
+                    bits |= $sign_bit;
+                }
+                if meta.signaling {
+                    // the payload is never zero, so this stays a NaN
+                    bits &= !$quiet_bit;
+                }
+                unsafe { transmute(bits) }
+            }
+
+            fn get_meta(self) -> Option<NanMeta> {
+                if !self.is_payloaded() {
+                    return None;
+                }
+                let bits: $u = unsafe { transmute(self) };
+                Some(NanMeta {
+                    negative: bits & $sign_bit != 0,
+                    signaling: bits & $quiet_bit == 0,
+                })
+            }
         }
     }
 }
 
-impl_NanPack!(f64, u64, F64_PAYLOAD_MASK, F64_EMPTY_NAN);
-impl_NanPack!(f32, u32, F32_PAYLOAD_MASK, F32_EMPTY_NAN);
+impl_NanPack!(f64, u64, F64_PAYLOAD_MASK, F64_EMPTY_NAN, F64_SIGN_BIT, F64_QUIET_BIT);
+impl_NanPack!(f32, u32, F32_PAYLOAD_MASK, F32_EMPTY_NAN, F32_SIGN_BIT, F32_QUIET_BIT);
 
 
 #[cfg(test)]
@@ -84,15 +168,119 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
+    fn capacity() {
+        assert_eq!(<f32 as NanPack<usize>>::capacity(), 0x3F_FFFD);
+        let last: f32 = NanPack::set_payload(0x3F_FFFC);
+        assert!(!last.is_exhausted());
+        assert!(!last.is_reserved());
+        assert_eq!(last.get_payload(), Some(0x3F_FFFC));
+    }
+
+    #[test]
     fn overflow_f32() {
-        let _: f32 = NanPack::set_payload(0x20_0000 as usize);
+        let f: f32 = NanPack::set_payload(0x3F_FFFD_usize);
+        assert!(f.is_nan());
+        assert!(f.is_payloaded());
+        assert!(f.is_exhausted());
+        assert_eq!(f.get_payload(), None);
+    }
+
+    #[test]
+    fn reserved() {
+        let f: f64 = NanPack::reserved();
+        assert!(f.is_nan());
+        assert!(f.is_reserved());
+        assert!(!f.is_payloaded());
+        assert_eq!(f.get_payload(), None);
+        assert!(!::std::f64::NAN.is_reserved());
+        let f: f32 = NanPack::reserved();
+        assert!(f.is_reserved() && !f.is_payloaded() && !f.is_exhausted());
     }
 
     #[test]
-    #[should_panic]
     #[cfg(target_pointer_width = "64")]
     fn overflow_f64() {
-        let _: f64 = NanPack::set_payload(::std::usize::MAX);
+        let f: f64 = NanPack::set_payload(::std::usize::MAX);
+        assert!(f.is_exhausted());
+        assert_eq!(f.get_payload(), None);
+    }
+
+    const METAS: [NanMeta; 4] = [
+        NanMeta { negative: false, signaling: false },
+        NanMeta { negative: true, signaling: false },
+        NanMeta { negative: false, signaling: true },
+        NanMeta { negative: true, signaling: true },
+    ];
+
+    #[test]
+    fn meta_roundtrip() {
+        for &meta in &METAS {
+            for i in 0..100 {
+                let i = i * 4931;
+                let f: f64 = NanPack::set_payload_with_meta(i, meta);
+                assert!(f.is_nan());
+                assert_eq!(f.get_payload(), Some(i));
+                assert_eq!(f.get_meta(), Some(meta));
+
+                let f: f32 = NanPack::set_payload_with_meta(i, meta);
+                assert!(f.is_nan());
+                assert_eq!(f.get_payload(), Some(i));
+                assert_eq!(f.get_meta(), Some(meta));
+            }
+        }
+        assert_eq!(::std::f64::NAN.get_meta(), None);
+    }
+
+    #[test]
+    fn survives_sign_operations() {
+        use num_traits::Float;
+        use {AsRaw, Dirty};
+
+        for &meta in &METAS {
+            for i in 0..100 {
+                let i = i * 4931;
+                let f: f64 = NanPack::set_payload_with_meta(i, meta);
+                let flipped = NanMeta { negative: !meta.negative, ..meta };
+                let positive = NanMeta { negative: false, ..meta };
+                let negative = NanMeta { negative: true, ..meta };
+
+                assert_eq!((-f).get_payload(), Some(i));
+                assert_eq!((-f).get_meta(), Some(flipped));
+                assert_eq!((-Dirty::new(f)).as_raw().get_meta(), Some(flipped));
+                assert_eq!(Float::abs(f).get_payload(), Some(i));
+                assert_eq!(Float::abs(f).get_meta(), Some(positive));
+                assert_eq!(f.copysign(-1.0).get_payload(), Some(i));
+                assert_eq!(f.copysign(-1.0).get_meta(), Some(negative));
+                assert_eq!(f.copysign(1.0).get_meta(), Some(positive));
+                assert_eq!(1f64.copysign(f), if meta.negative { -1.0 } else { 1.0 });
+
+                let f: f32 = NanPack::set_payload_with_meta(i, meta);
+                assert_eq!((-f).get_payload(), Some(i));
+                assert_eq!((-f).get_meta(), Some(flipped));
+                assert_eq!(Float::abs(f).get_meta(), Some(positive));
+                assert_eq!(f.copysign(-1.0).get_meta(), Some(negative));
+            }
+        }
+    }
+
+    #[test]
+    fn survives_widening_roundtrip() {
+        // widening shifts the f32 payload into the upper mantissa bits and narrowing shifts it
+        // back. Signaling NaNs are quieted on the way.
+        for &meta in &METAS {
+            for i in 0..100 {
+                let i = i * 4931;
+                let f: f32 = NanPack::set_payload_with_meta(i, meta);
+                let wide = f as f64;
+                assert!(wide.is_nan());
+                assert_eq!(wide.is_sign_negative(), meta.negative);
+                let narrow = wide as f32;
+                assert_eq!(narrow.get_payload(), Some(i));
+                assert_eq!(
+                    narrow.get_meta(),
+                    Some(NanMeta { negative: meta.negative, signaling: false })
+                );
+            }
+        }
     }
 }
@@ -0,0 +1,234 @@
+// Copyright 2017 Matthias Tellen
+//
+// Permission is hereby granted,  free of charge,  to any person  obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without  limitation  the  rights to use,  copy,  modify,  merge,  publish,  distribute,
+// sublicense,  and/or sell copies of the Software,  and to permit  persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The  above  copyright  notice and this permission notice shall be included  in all copies or sub-
+// stantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS",  WITHOUT WARRANTY OF ANY KIND,  EXPRESS OR IMPLIED,  INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,  FITNESS  FOR A PARTICULAR PURPOSE AND NON-
+// INFRINGEMENT.  IN NO EVENT SHALL THE AUTHORS  OR  COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAM-
+// AGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,  TORT OR OTHERWISE, ARISING FROM, OUT
+// OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use core::fmt;
+use num_traits::float::Float;
+use super::{AsRaw, Clean, UncheckedConv};
+use ::nanpack::NanPack;
+
+/// An optional `Clean` value that is as big as the float itself.
+///
+/// Since a `Clean` is never NaN, `None` is stored as the reserved NaN of `NanPack`, which is
+/// distinct from every NaN that carries an error. `Option<Clean<f64>>` takes 16 bytes,
+/// `OptionClean<f64>` 8.
+#[derive(Copy, Clone)]
+pub struct OptionClean<F: Float>(F);
+
+impl<F> OptionClean<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    pub fn some(c: Clean<F>) -> Self {
+        OptionClean(c.as_raw())
+    }
+
+    #[inline]
+    pub fn none() -> Self {
+        OptionClean(F::reserved())
+    }
+
+    #[inline]
+    pub fn is_some(&self) -> bool {
+        !self.is_none()
+    }
+
+    #[inline]
+    pub fn is_none(&self) -> bool {
+        self.0.is_reserved()
+    }
+
+    #[inline]
+    pub fn get(self) -> Option<Clean<F>> {
+        if self.is_none() {
+            None
+        } else {
+            Some(Clean::from_raw(self.0))
+        }
+    }
+
+    #[inline]
+    pub fn unwrap(self) -> Clean<F> {
+        self.expect("called `OptionClean::unwrap()` on a `None` value")
+    }
+
+    #[inline]
+    pub fn expect(self, msg: &str) -> Clean<F> {
+        self.get().expect(msg)
+    }
+
+    #[inline]
+    pub fn unwrap_or(self, default: Clean<F>) -> Clean<F> {
+        self.get().unwrap_or(default)
+    }
+
+    #[inline]
+    pub fn unwrap_or_else<C: FnOnce() -> Clean<F>>(self, c: C) -> Clean<F> {
+        self.get().unwrap_or_else(c)
+    }
+
+    #[inline]
+    pub fn map<C: FnOnce(Clean<F>) -> Clean<F>>(self, c: C) -> Self {
+        self.get().map(c).into()
+    }
+
+    #[inline]
+    pub fn map_or<U, C: FnOnce(Clean<F>) -> U>(self, default: U, c: C) -> U {
+        self.get().map_or(default, c)
+    }
+
+    #[inline]
+    pub fn and_then<C: FnOnce(Clean<F>) -> Self>(self, c: C) -> Self {
+        match self.get() {
+            Some(x) => c(x),
+            None => Self::none(),
+        }
+    }
+
+    #[inline]
+    pub fn filter<P: FnOnce(Clean<F>) -> bool>(self, predicate: P) -> Self {
+        self.get().filter(|&x| predicate(x)).into()
+    }
+
+    #[inline]
+    pub fn or(self, other: Self) -> Self {
+        if self.is_some() { self } else { other }
+    }
+
+    #[inline]
+    pub fn or_else<C: FnOnce() -> Self>(self, c: C) -> Self {
+        if self.is_some() { self } else { c() }
+    }
+
+    #[inline]
+    pub fn take(&mut self) -> Self {
+        ::core::mem::take(self)
+    }
+
+    #[inline]
+    pub fn replace(&mut self, c: Clean<F>) -> Self {
+        ::core::mem::replace(self, Self::some(c))
+    }
+}
+
+impl<F> Default for OptionClean<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl<F> From<Option<Clean<F>>> for OptionClean<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    fn from(o: Option<Clean<F>>) -> Self {
+        match o {
+            Some(c) => OptionClean::some(c),
+            None => OptionClean::none(),
+        }
+    }
+}
+
+impl<F> From<Clean<F>> for OptionClean<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    fn from(c: Clean<F>) -> Self {
+        OptionClean::some(c)
+    }
+}
+
+impl<F> From<OptionClean<F>> for Option<Clean<F>>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    fn from(o: OptionClean<F>) -> Self {
+        o.get()
+    }
+}
+
+impl<F> PartialEq for OptionClean<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.get() == other.get()
+    }
+}
+
+impl<F> fmt::Debug for OptionClean<F>
+where
+    F: Float + NanPack<usize> + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.get().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+    use super::*;
+    use {F32, F64};
+
+    #[test]
+    fn size() {
+        assert_eq!(size_of::<OptionClean<f64>>(), size_of::<f64>());
+        assert_eq!(size_of::<OptionClean<f32>>(), size_of::<f32>());
+    }
+
+    #[test]
+    fn combinators() {
+        let one = F64::try_new(1.0).unwrap();
+        let two = F64::try_new(2.0).unwrap();
+        let some = OptionClean::some(one);
+        let none = OptionClean::<f64>::none();
+
+        assert!(some.is_some());
+        assert!(none.is_none());
+        assert_eq!(some.get(), Some(one));
+        assert_eq!(none.get(), None);
+        assert_eq!(none.unwrap_or(two), two);
+        assert_eq!(some.map(|x| (x + x).sanitize().unwrap()).unwrap(), two);
+        assert!(none.map(|x| x).is_none());
+        assert!(some.filter(|x| x > 1.0).is_none());
+        assert_eq!(none.or(some), some);
+        assert_eq!(OptionClean::from(Some(two)).and_then(|_| none), none);
+        assert_eq!(format!("{:?}", none), "None");
+        assert_eq!(OptionClean::<f32>::default(), OptionClean::none());
+
+        let mut x = OptionClean::from(F32::try_new(3.0).unwrap());
+        assert_eq!(x.take().unwrap(), 3.0);
+        assert!(x.is_none());
+    }
+
+    #[test]
+    fn special_values() {
+        // infinities and negative zero are valid values
+        for &f in &[::std::f64::INFINITY, ::std::f64::NEG_INFINITY, -0.0] {
+            assert!(OptionClean::some(F64::try_new(f).unwrap()).is_some());
+        }
+    }
+}
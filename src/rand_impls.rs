@@ -0,0 +1,296 @@
+// Copyright 2017 Matthias Tellen
+//
+// Permission is hereby granted,  free of charge,  to any person  obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without  limitation  the  rights to use,  copy,  modify,  merge,  publish,  distribute,
+// sublicense,  and/or sell copies of the Software,  and to permit  persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The  above  copyright  notice and this permission notice shall be included  in all copies or sub-
+// stantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS",  WITHOUT WARRANTY OF ANY KIND,  EXPRESS OR IMPLIED,  INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,  FITNESS  FOR A PARTICULAR PURPOSE AND NON-
+// INFRINGEMENT.  IN NO EVENT SHALL THE AUTHORS  OR  COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAM-
+// AGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,  TORT OR OTHERWISE, ARISING FROM, OUT
+// OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Sampling `Clean` values, enabled with the `rand` feature.
+//!
+//! The distributions check their parameters when they are created, so sampling can't produce a
+//! NaN afterwards. Values that are drawn from `(0, 1]` never hit the logarithm of zero.
+
+use num_traits::float::Float;
+use rand::distributions::{Distribution, Standard};
+use rand::Rng;
+use super::{AsRaw, Clean, UncheckedConv};
+use ::approx::Ulp;
+use ::error::FloatError;
+use ::nanpack::NanPack;
+
+impl<F> Distribution<Clean<F>> for Standard
+where
+    F: Float + NanPack<usize>,
+    Standard: Distribution<F>,
+{
+    /// Samples from `[0, 1)`, like `Standard` does for the raw floats.
+    #[inline]
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Clean<F> {
+        UnitInterval.sample(rng)
+    }
+}
+
+// a sample from `(0, 1]`
+#[inline]
+fn open_closed<F, R: Rng + ?Sized>(rng: &mut R) -> F
+where
+    F: Float,
+    Standard: Distribution<F>,
+{
+    F::one() - Standard.sample(rng)
+}
+
+/// Samples uniformly from `[0, 1)`.
+#[derive(Debug, Copy, Clone)]
+pub struct UnitInterval;
+
+impl<F> Distribution<Clean<F>> for UnitInterval
+where
+    F: Float + NanPack<usize>,
+    Standard: Distribution<F>,
+{
+    #[inline]
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Clean<F> {
+        Clean::from_raw(Standard.sample(rng))
+    }
+}
+
+/// Samples uniformly from `[low, high)`.
+#[derive(Debug, Copy, Clone)]
+pub struct Uniform<F: Float> {
+    low: F,
+    range: F,
+    high: F,
+}
+
+impl<F> Uniform<F>
+where
+    F: Float + NanPack<usize>,
+{
+    /// Fails unless `low < high` and both as well as their distance are finite.
+    #[track_caller]
+    pub fn new(low: Clean<F>, high: Clean<F>) -> Result<Self, FloatError> {
+        let (l, h) = (low.as_raw(), high.as_raw());
+        if !l.is_finite() {
+            return Err(FloatError::invalid_distribution("uniform", "lower bound", l));
+        }
+        if !h.is_finite() || h <= l || !(h - l).is_finite() {
+            return Err(FloatError::invalid_distribution("uniform", "upper bound", h));
+        }
+        Ok(Uniform {
+            low: l,
+            range: h - l,
+            high: h,
+        })
+    }
+}
+
+impl<F> Distribution<Clean<F>> for Uniform<F>
+where
+    F: Float + NanPack<usize> + Ulp,
+    Standard: Distribution<F>,
+{
+    #[inline]
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Clean<F> {
+        let x = self.low + self.range * Standard.sample(rng);
+        // rounding can reach the upper bound
+        Clean::from_raw(if x < self.high { x } else { self.high.next_down() })
+    }
+}
+
+/// The normal distribution.
+#[derive(Debug, Copy, Clone)]
+pub struct Normal<F: Float> {
+    mean: F,
+    std_dev: F,
+}
+
+impl<F> Normal<F>
+where
+    F: Float + NanPack<usize>,
+{
+    /// Fails unless the mean is finite and the standard deviation finite and not negative.
+    #[track_caller]
+    pub fn new(mean: Clean<F>, std_dev: Clean<F>) -> Result<Self, FloatError> {
+        let (m, s) = (mean.as_raw(), std_dev.as_raw());
+        if !m.is_finite() {
+            return Err(FloatError::invalid_distribution("normal", "mean", m));
+        }
+        if !s.is_finite() || s < F::zero() {
+            return Err(FloatError::invalid_distribution("normal", "standard deviation", s));
+        }
+        Ok(Normal { mean: m, std_dev: s })
+    }
+
+    // a standard normal sample with the Box-Muller transform
+    #[inline]
+    fn standard<R: Rng + ?Sized>(rng: &mut R) -> F
+    where
+        Standard: Distribution<F>,
+    {
+        let u: F = open_closed(rng);
+        let v: F = Standard.sample(rng);
+        let two = F::one() + F::one();
+        let tau = F::from(::core::f64::consts::PI).unwrap_or_else(F::zero) * two;
+        (-two * u.ln()).sqrt() * (tau * v).cos()
+    }
+}
+
+impl<F> Distribution<Clean<F>> for Normal<F>
+where
+    F: Float + NanPack<usize>,
+    Standard: Distribution<F>,
+{
+    #[inline]
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Clean<F> {
+        Clean::from_raw(self.mean + self.std_dev * Normal::standard(rng))
+    }
+}
+
+/// The log-normal distribution, i.e. `exp` of a normal distribution.
+#[derive(Debug, Copy, Clone)]
+pub struct LogNormal<F: Float> {
+    normal: Normal<F>,
+}
+
+impl<F> LogNormal<F>
+where
+    F: Float + NanPack<usize>,
+{
+    /// Takes the parameters of the underlying normal distribution.
+    #[track_caller]
+    pub fn new(mu: Clean<F>, sigma: Clean<F>) -> Result<Self, FloatError> {
+        Normal::new(mu, sigma).map(|normal| LogNormal { normal })
+    }
+}
+
+impl<F> Distribution<Clean<F>> for LogNormal<F>
+where
+    F: Float + NanPack<usize>,
+    Standard: Distribution<F>,
+{
+    #[inline]
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Clean<F> {
+        self.normal.sample(rng).map(Float::exp)
+    }
+}
+
+/// The exponential distribution with rate `lambda`.
+#[derive(Debug, Copy, Clone)]
+pub struct Exp<F: Float> {
+    lambda: F,
+}
+
+impl<F> Exp<F>
+where
+    F: Float + NanPack<usize>,
+{
+    /// Fails unless `lambda` is positive and finite.
+    #[track_caller]
+    pub fn new(lambda: Clean<F>) -> Result<Self, FloatError> {
+        let l = lambda.as_raw();
+        if !l.is_finite() || l <= F::zero() {
+            return Err(FloatError::invalid_distribution("exponential", "rate", l));
+        }
+        Ok(Exp { lambda: l })
+    }
+}
+
+impl<F> Distribution<Clean<F>> for Exp<F>
+where
+    F: Float + NanPack<usize>,
+    Standard: Distribution<F>,
+{
+    #[inline]
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Clean<F> {
+        let u: F = open_closed(rng);
+        Clean::from_raw(-u.ln() / self.lambda)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+    use F64;
+
+    // xorshift64*, good enough for tests and without another dependency
+    struct TestRng(u64);
+
+    impl RngCore for TestRng {
+        fn next_u32(&mut self) -> u32 {
+            (self.next_u64() >> 32) as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            for chunk in dest.chunks_mut(8) {
+                let bytes = self.next_u64().to_le_bytes();
+                chunk.copy_from_slice(&bytes[..chunk.len()]);
+            }
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), ::rand::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    fn c(f: f64) -> F64 {
+        F64::try_new(f).unwrap()
+    }
+
+    fn mean<D: Distribution<F64>>(d: D) -> f64 {
+        let mut rng = TestRng(0x1234_5678);
+        let n = 20_000;
+        (0..n).map(|_| d.sample(&mut rng).as_raw()).sum::<f64>() / n as f64
+    }
+
+    #[test]
+    fn parameters() {
+        let inf = ::std::f64::INFINITY;
+        assert!(Uniform::new(c(1.0), c(1.0)).is_err());
+        assert!(Uniform::new(c(-inf), c(1.0)).is_err());
+        assert!(Uniform::new(c(-::std::f64::MAX), c(::std::f64::MAX)).is_err());
+        assert!(Normal::new(c(0.0), c(-1.0)).is_err());
+        assert!(Normal::new(c(0.0), c(0.0)).is_ok());
+        assert!(Exp::new(c(0.0)).is_err());
+        let err = format!("{}", Exp::new(c(inf)).unwrap_err());
+        assert!(err.ends_with("Invalid rate infinity for the exponential distribution"));
+    }
+
+    #[test]
+    fn samples() {
+        let mut rng = TestRng(42);
+        let u = Uniform::new(c(-1.0), c(1.0)).unwrap();
+        for _ in 0..1000 {
+            let x: F64 = rng.gen();
+            assert!((0.0..1.0).contains(&x.as_raw()));
+            let x = u.sample(&mut rng);
+            assert!((-1.0..1.0).contains(&x.as_raw()));
+            assert!(Exp::new(c(2.0)).unwrap().sample(&mut rng) >= 0.0);
+            assert!(LogNormal::new(c(0.0), c(1.0)).unwrap().sample(&mut rng) >= 0.0);
+        }
+        assert!((mean(Normal::new(c(3.0), c(2.0)).unwrap()) - 3.0).abs() < 0.1);
+        assert!((mean(Exp::new(c(4.0)).unwrap()) - 0.25).abs() < 0.01);
+        assert!((mean(u)).abs() < 0.05);
+        let x: Clean<f32> = UnitInterval.sample(&mut rng);
+        assert!(x < 1.0);
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright 2017 Matthias Tellen
+//
+// Permission is hereby granted,  free of charge,  to any person  obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without  limitation  the  rights to use,  copy,  modify,  merge,  publish,  distribute,
+// sublicense,  and/or sell copies of the Software,  and to permit  persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The  above  copyright  notice and this permission notice shall be included  in all copies or sub-
+// stantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS",  WITHOUT WARRANTY OF ANY KIND,  EXPRESS OR IMPLIED,  INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,  FITNESS  FOR A PARTICULAR PURPOSE AND NON-
+// INFRINGEMENT.  IN NO EVENT SHALL THE AUTHORS  OR  COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAM-
+// AGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,  TORT OR OTHERWISE, ARISING FROM, OUT
+// OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Serde support, enabled with the `serde` feature.
+//!
+//! Human readable formats like JSON can't represent NaN and the infinities as numbers, so both
+//! types write them as the strings `"NaN"`, `"inf"` and `"-inf"`. Binary formats get the raw
+//! float.
+//!
+//! `Clean` is checked with `Clean::try_new` when it's deserialized, so a NaN is rejected with the
+//! text of the `FloatError`. The error a NaN in a `Dirty` refers to lives in this process only and
+//! doesn't travel with it.
+
+use core::fmt;
+use core::marker::PhantomData;
+use num_traits::float::Float;
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer};
+use super::{AsRaw, Clean, Dirty, UncheckedConv};
+use ::nanpack::NanPack;
+
+impl<F> Serialize for Clean<F>
+where
+    F: Float + Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_float(self.as_raw(), serializer)
+    }
+}
+
+impl<'de, F> Deserialize<'de> for Clean<F>
+where
+    F: Float + NanPack<usize> + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let f = Dirty::<F>::deserialize(deserializer)?;
+        Clean::try_new(f.as_raw()).map_err(de::Error::custom)
+    }
+}
+
+impl<F> Serialize for Dirty<F>
+where
+    F: Float + Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_float(self.as_raw(), serializer)
+    }
+}
+
+fn serialize_float<F, S>(f: F, serializer: S) -> Result<S::Ok, S::Error>
+where
+    F: Float + Serialize,
+    S: Serializer,
+{
+    if !serializer.is_human_readable() || f.is_finite() {
+        f.serialize(serializer)
+    } else if f.is_nan() {
+        serializer.serialize_str("NaN")
+    } else if f.is_sign_positive() {
+        serializer.serialize_str("inf")
+    } else {
+        serializer.serialize_str("-inf")
+    }
+}
+
+impl<'de, F> Deserialize<'de> for Dirty<F>
+where
+    F: Float + NanPack<usize> + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(DirtyVisitor(PhantomData))
+        } else {
+            F::deserialize(deserializer).map(Dirty::from_raw)
+        }
+    }
+}
+
+struct DirtyVisitor<F>(PhantomData<F>);
+
+impl<F> DirtyVisitor<F>
+where
+    F: Float,
+{
+    fn cast<T: Into<f64>, E: de::Error>(v: T) -> Result<Dirty<F>, E> {
+        F::from(v.into())
+            .map(Dirty::from_raw)
+            .ok_or_else(|| E::custom("number out of range"))
+    }
+}
+
+impl<'de, F> Visitor<'de> for DirtyVisitor<F>
+where
+    F: Float,
+{
+    type Value = Dirty<F>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a number or one of \"NaN\", \"inf\" and \"-inf\"")
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        Self::cast(v)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        Self::cast(v as f64)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        Self::cast(v as f64)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        match v {
+            "NaN" => Ok(Dirty::from_raw(F::nan())),
+            "inf" => Ok(Dirty::from_raw(F::infinity())),
+            "-inf" => Ok(Dirty::from_raw(F::neg_infinity())),
+            _ => Err(E::invalid_value(de::Unexpected::Str(v), &self)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde::de::IntoDeserializer;
+    use serde::de::value::{self, F64Deserializer};
+    use serde_json;
+    use {DirtyF32, DirtyF64, F64};
+
+    #[test]
+    fn clean() {
+        let f = F64::try_new(1.5).unwrap();
+        assert_eq!(serde_json::to_string(&f).unwrap(), "1.5");
+        assert_eq!(serde_json::from_str::<F64>("1.5").unwrap(), f);
+        assert_eq!(serde_json::from_str::<Vec<F64>>("[1, -2]").unwrap(), vec![1.0, -2.0]);
+    }
+
+    #[test]
+    fn clean_roundtrip_infinity() {
+        for &f in &[::std::f64::INFINITY, ::std::f64::NEG_INFINITY] {
+            let json = serde_json::to_string(&F64::try_new(f).unwrap()).unwrap();
+            assert_eq!(serde_json::from_str::<F64>(&json).unwrap(), f);
+        }
+        assert!(serde_json::from_str::<F64>("\"NaN\"").is_err());
+    }
+
+    #[test]
+    fn clean_rejects_nan() {
+        // JSON has no NaN, so the value is handed in directly
+        let deserializer: F64Deserializer<value::Error> = ::std::f64::NAN.into_deserializer();
+        let msg = format!("{}", F64::deserialize(deserializer).err().unwrap());
+        assert!(msg.contains("Sanitization of NaN"), "{}", msg);
+    }
+
+    #[test]
+    fn dirty_roundtrip() {
+        for &f in &[1.5, ::std::f64::INFINITY, ::std::f64::NEG_INFINITY] {
+            let json = serde_json::to_string(&DirtyF64::new(f)).unwrap();
+            assert_eq!(serde_json::from_str::<DirtyF64>(&json).unwrap(), f);
+        }
+        let json = serde_json::to_string(&DirtyF32::new(::std::f32::NAN)).unwrap();
+        assert_eq!(json, "\"NaN\"");
+        let nan: DirtyF32 = serde_json::from_str(&json).unwrap();
+        assert!(nan.sanitize().is_err());
+        assert!(serde_json::from_str::<DirtyF64>("\"nan\"").is_err());
+    }
+}
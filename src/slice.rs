@@ -0,0 +1,301 @@
+// Copyright 2017 Matthias Tellen
+//
+// Permission is hereby granted,  free of charge,  to any person  obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without  limitation  the  rights to use,  copy,  modify,  merge,  publish,  distribute,
+// sublicense,  and/or sell copies of the Software,  and to permit  persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The  above  copyright  notice and this permission notice shall be included  in all copies or sub-
+// stantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS",  WITHOUT WARRANTY OF ANY KIND,  EXPRESS OR IMPLIED,  INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,  FITNESS  FOR A PARTICULAR PURPOSE AND NON-
+// INFRINGEMENT.  IN NO EVENT SHALL THE AUTHORS  OR  COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAM-
+// AGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,  TORT OR OTHERWISE, ARISING FROM, OUT
+// OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Conversions and sanitization of whole slices and vectors at once.
+//!
+//! `Clean` and `Dirty` are `#[repr(transparent)]`, so slices of them can be reinterpreted as
+//! slices of raw floats and handed to C as-is. Only the casts that can't smuggle a NaN into a
+//! `Clean` are offered.
+//!
+//! Sanitization scans the values in chunks without branching, which the compiler can vectorize.
+//! Only chunks that contain a NaN are looked at again to collect the errors.
+
+#[cfg(feature = "alloc")]
+use core::fmt;
+#[cfg(feature = "alloc")]
+use core::mem::ManuallyDrop;
+use core::slice;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+#[cfg(feature = "alloc")]
+use failure::Fail;
+use num_traits::float::Float;
+use super::{Clean, Dirty};
+#[cfg(feature = "alloc")]
+use ::error::FloatError;
+#[cfg(feature = "alloc")]
+use ::nanpack::NanPack;
+
+#[cfg(feature = "alloc")]
+const CHUNK_SIZE: usize = 16;
+
+// All casts below rely on `Clean<F>` and `Dirty<F>` having the layout of `F`.
+
+#[inline]
+pub fn clean_as_raw<F: Float>(xs: &[Clean<F>]) -> &[F] {
+    unsafe { slice::from_raw_parts(xs.as_ptr() as *const F, xs.len()) }
+}
+
+#[inline]
+pub fn clean_as_dirty<F: Float>(xs: &[Clean<F>]) -> &[Dirty<F>] {
+    unsafe { slice::from_raw_parts(xs.as_ptr() as *const Dirty<F>, xs.len()) }
+}
+
+#[inline]
+pub fn dirty_as_raw<F: Float>(xs: &[Dirty<F>]) -> &[F] {
+    unsafe { slice::from_raw_parts(xs.as_ptr() as *const F, xs.len()) }
+}
+
+#[inline]
+pub fn dirty_as_raw_mut<F: Float>(xs: &mut [Dirty<F>]) -> &mut [F] {
+    unsafe { slice::from_raw_parts_mut(xs.as_mut_ptr() as *mut F, xs.len()) }
+}
+
+#[inline]
+pub fn raw_as_dirty<F: Float>(xs: &[F]) -> &[Dirty<F>] {
+    unsafe { slice::from_raw_parts(xs.as_ptr() as *const Dirty<F>, xs.len()) }
+}
+
+#[inline]
+pub fn raw_as_dirty_mut<F: Float>(xs: &mut [F]) -> &mut [Dirty<F>] {
+    unsafe { slice::from_raw_parts_mut(xs.as_mut_ptr() as *mut Dirty<F>, xs.len()) }
+}
+
+/// The errors of every NaN in a slice, ordered by index.
+#[cfg(feature = "alloc")]
+pub struct SliceFloatError {
+    errors: Vec<(usize, FloatError)>,
+}
+
+#[cfg(feature = "alloc")]
+impl SliceFloatError {
+    pub fn errors(&self) -> &[(usize, FloatError)] {
+        &self.errors
+    }
+
+    pub fn indices<'a>(&'a self) -> impl Iterator<Item = usize> + 'a {
+        self.errors.iter().map(|&(i, _)| i)
+    }
+
+    pub fn into_errors(self) -> Vec<(usize, FloatError)> {
+        self.errors
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl fmt::Display for SliceFloatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} NaN(s) in slice", self.errors.len())?;
+        for &(i, ref err) in &self.errors {
+            write!(f, "\n  [{}] {}", i, err)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl fmt::Debug for SliceFloatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Fail for SliceFloatError {}
+
+#[cfg(feature = "alloc")]
+#[inline]
+fn has_nan<F: Float>(chunk: &[F]) -> bool {
+    // no early return, so that the loop can be vectorized
+    chunk.iter().fold(false, |acc, x| acc | x.is_nan())
+}
+
+#[cfg(feature = "alloc")]
+fn check<F>(xs: &[F]) -> Result<(), SliceFloatError>
+where
+    F: Float + NanPack<usize>,
+{
+    let mut errors = Vec::new();
+    for (n, chunk) in xs.chunks(CHUNK_SIZE).enumerate() {
+        if !has_nan(chunk) {
+            continue;
+        }
+        for (i, &x) in chunk.iter().enumerate() {
+            if let Err(err) = Clean::try_new(x) {
+                errors.push((n * CHUNK_SIZE + i, err));
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(SliceFloatError { errors })
+    }
+}
+
+/// Sanitizes all values of a slice without copying them.
+#[cfg(feature = "alloc")]
+pub fn sanitize_slice<F>(xs: &[F]) -> Result<&[Clean<F>], SliceFloatError>
+where
+    F: Float + NanPack<usize>,
+{
+    check(xs)?;
+    // `Clean<F>` is a transparent wrapper and none of the values are NaN
+    Ok(unsafe { slice::from_raw_parts(xs.as_ptr() as *const Clean<F>, xs.len()) })
+}
+
+/// Like `sanitize_slice`, for mutable slices. Only `Clean` values can be written through the
+/// result, so it stays sanitized.
+#[cfg(feature = "alloc")]
+pub fn sanitize_slice_mut<F>(xs: &mut [F]) -> Result<&mut [Clean<F>], SliceFloatError>
+where
+    F: Float + NanPack<usize>,
+{
+    check(xs)?;
+    Ok(unsafe { slice::from_raw_parts_mut(xs.as_mut_ptr() as *mut Clean<F>, xs.len()) })
+}
+
+/// Sanitizes all values of a vector, reusing its allocation.
+#[cfg(feature = "alloc")]
+pub fn sanitize_vec<F>(xs: Vec<F>) -> Result<Vec<Clean<F>>, SliceFloatError>
+where
+    F: Float + NanPack<usize>,
+{
+    check(&xs)?;
+    let mut xs = ManuallyDrop::new(xs);
+    let (ptr, len, cap) = (xs.as_mut_ptr(), xs.len(), xs.capacity());
+    // same layout as above, and the allocation is handed over unchanged
+    Ok(unsafe { Vec::from_raw_parts(ptr as *mut Clean<F>, len, cap) })
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use std::f64::NAN;
+    use F64;
+
+    #[test]
+    fn clean() {
+        let xs: Vec<f64> = (0..100).map(|i| i as f64).collect();
+        let clean = sanitize_slice(&xs).unwrap();
+        assert_eq!(clean.len(), 100);
+        assert_eq!(clean.as_ptr() as *const f64, xs.as_ptr());
+        assert_eq!(clean[42], 42.0);
+        assert!(sanitize_slice::<f32>(&[]).unwrap().is_empty());
+
+        let ptr = xs.as_ptr();
+        let clean = sanitize_vec(xs).unwrap();
+        assert_eq!(clean.as_ptr() as *const f64, ptr);
+        assert_eq!(clean.iter().fold(F64::try_new(0.0).unwrap(), |a, &b| a.max(b)), 99.0);
+    }
+
+    #[test]
+    fn mutable() {
+        let mut xs = [1.0, 2.0, 3.0];
+        {
+            let clean = sanitize_slice_mut(&mut xs).unwrap();
+            clean[0] = F64::try_new(4.0).unwrap();
+        }
+        assert_eq!(xs[0], 4.0);
+        assert!(sanitize_slice_mut(&mut [NAN]).is_err());
+    }
+
+    #[test]
+    fn casts() {
+        use core::mem::{align_of, size_of};
+        use {AsRaw, Dirty};
+
+        assert_eq!(size_of::<Clean<f64>>(), size_of::<f64>());
+        assert_eq!(align_of::<Clean<f32>>(), align_of::<f32>());
+        assert_eq!(size_of::<Dirty<f32>>(), size_of::<f32>());
+        assert_eq!(align_of::<Dirty<f64>>(), align_of::<f64>());
+
+        let mut xs = [1.0, NAN, 3.0];
+        {
+            let dirty = raw_as_dirty_mut(&mut xs);
+            dirty[0] = dirty[0] / Dirty::new(0.0);
+            assert!(dirty[1].sanitize().is_err());
+            assert_eq!(dirty_as_raw(dirty)[2], 3.0);
+        }
+        assert_eq!(xs[0], ::std::f64::INFINITY);
+        dirty_as_raw_mut(raw_as_dirty_mut(&mut xs))[1] = 2.0;
+        let clean = sanitize_slice(&xs).unwrap();
+        assert_eq!(clean_as_raw(clean), &xs);
+        assert_eq!(clean_as_dirty(clean)[1].as_raw(), 2.0);
+        assert_eq!(raw_as_dirty(&xs).len(), 3);
+    }
+
+    // stands in for a C library that works on buffers
+    extern "C" fn scale(xs: *mut f64, len: usize, k: f64) {
+        let xs = unsafe { ::std::slice::from_raw_parts_mut(xs, len) };
+        for x in xs {
+            *x *= k;
+        }
+    }
+
+    extern "C" {
+        fn cbrt(x: Clean<f64>) -> Dirty<f64>;
+        fn sqrt(x: f64) -> f64;
+    }
+
+    #[test]
+    fn ffi() {
+        use core::mem::transmute;
+        use {AsRaw, Dirty};
+
+        let mut xs = vec![1.0, 2.0, 4.0];
+        {
+            let dirty = raw_as_dirty_mut(&mut xs);
+            scale(dirty.as_mut_ptr() as *mut f64, dirty.len(), 0.5);
+        }
+        let clean = sanitize_vec(xs).unwrap();
+        assert_eq!(clean_as_raw(&clean), &[0.5, 1.0, 2.0]);
+
+        // values are passed in the same registers as the raw floats
+        let eight = F64::try_new(8.0).unwrap();
+        assert_eq!(unsafe { cbrt(eight) }.as_raw(), 2.0);
+        let sqrt_dirty: extern "C" fn(Dirty<f64>) -> Dirty<f64> =
+            unsafe { transmute(sqrt as unsafe extern "C" fn(f64) -> f64) };
+        assert!(sqrt_dirty(Dirty::new(-1.0)).sanitize().is_err());
+        assert_eq!(sqrt_dirty(Dirty::new(4.0)).as_raw(), 2.0);
+    }
+
+    #[test]
+    fn all_errors() {
+        let mut xs = vec![1.0; 100];
+        for &i in &[3, 17, 18, 99] {
+            xs[i] = NAN;
+        }
+        let err = sanitize_slice(&xs).unwrap_err();
+        assert_eq!(err.indices().collect::<Vec<_>>(), vec![3, 17, 18, 99]);
+        assert!(format!("{}", err).starts_with("4 NaN(s) in slice"));
+        assert_eq!(sanitize_vec(xs).unwrap_err().errors().len(), 4);
+    }
+
+    #[test]
+    #[cfg(all(feature = "alloc", not(build = "release")))]
+    fn tracked_errors() {
+        use AsRaw;
+
+        let zero = F64::try_new(0.0).unwrap();
+        let xs = [1.0, (zero / zero).as_raw(), 2.0];
+        let err = sanitize_slice(&xs).unwrap_err();
+        let (i, ref e) = err.errors()[0];
+        assert_eq!(i, 1);
+        assert!(format!("{}", e).ends_with("Division zero by zero resulted in NaN"));
+    }
+}
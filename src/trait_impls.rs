@@ -15,13 +15,29 @@
 // AGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,  TORT OR OTHERWISE, ARISING FROM, OUT
 // OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-use std::ops::{Add, Mul, Div, Neg};
-use std::cmp::Ordering;
-use std::fmt;
-use super::{Clean, Dirty, UncheckedConv};
-use ::error::{FloatError, FLOAT_ERROR_BUFFER};
+use core::ops::{Add, Sub, Mul, Div, Rem, Neg};
+use core::cmp::Ordering;
+use core::fmt;
+use core::num::FpCategory;
+use super::{AsRaw, Clean, Dirty, UncheckedConv};
+#[cfg(all(feature = "alloc", not(build = "release")))]
+use ::error::{range_warnings_enabled, FloatError, RangeOp, FLOAT_ERROR_BUFFER};
 use ::nanpack::NanPack;
 use num_traits::float::Float;
+use num_traits::{Bounded, FromPrimitive, Num, NumCast, One, Signed, ToPrimitive, Zero};
+
+// Passes on the error of `s` when both operands carry one. The error of `o` is removed from the
+// buffer, unless both refer to the same error.
+#[cfg(all(feature = "alloc", not(build = "release")))]
+#[inline]
+pub(crate) fn keep_first_error<F: Float + NanPack<usize>>(s: F, o: F) -> F {
+    if let (Some(a), Some(b)) = (s.get_payload(), o.get_payload()) {
+        if a != b {
+            FLOAT_ERROR_BUFFER.remove::<F>(b);
+        }
+    }
+    s
+}
 
 macro_rules! impl_common_traits {
     ( $( $name: path),* ) => {
@@ -29,42 +45,80 @@ macro_rules! impl_common_traits {
             impl<B, F> Add<B> for $name
             where
                 F: Float,
-                B: UncheckedConv<F>,
+                B: AsRaw<F>,
             {
                 type Output = Dirty<F>;
 
                 #[inline]
                 fn add(self, other: B) -> Self::Output {
-                    Dirty::from_raw(self.as_raw() + other.as_raw())
+                    let (s, o) = (self.as_raw(), other.as_raw());
+                    let result = s + o;
+                    #[cfg(all(feature = "alloc", not(build = "release")))]
+                    {
+                        if range_warnings_enabled() {
+                            if let Some(w) = FloatError::range(RangeOp::Add, s, o, result) {
+                                FLOAT_ERROR_BUFFER.warn(w);
+                            }
+                        }
+                    }
+                    Dirty::from_raw(result)
+                }
+            }
+
+            impl<B, F> Sub<B> for $name
+            where
+                F: Float,
+                B: AsRaw<F>,
+            {
+                type Output = Dirty<F>;
+
+                #[inline]
+                fn sub(self, other: B) -> Self::Output {
+                    let (s, o) = (self.as_raw(), other.as_raw());
+                    let result = s - o;
+                    #[cfg(all(feature = "alloc", not(build = "release")))]
+                    {
+                        if range_warnings_enabled() {
+                            if let Some(w) = FloatError::range(RangeOp::Sub, s, o, result) {
+                                FLOAT_ERROR_BUFFER.warn(w);
+                            }
+                        }
+                    }
+                    Dirty::from_raw(result)
                 }
             }
 
             impl<B, F> Mul<B> for $name
             where
                 F: Float + NanPack<usize>,
-                B: UncheckedConv<F> +  Copy,
+                B: AsRaw<F> +  Copy,
             {
                 type Output = Dirty<F>;
 
                 #[inline]
+                #[track_caller]
                 fn mul(self, other: B) -> Self::Output {
                     let s = self.as_raw();
                     let o = other.as_raw();
                     let result = s * o;
-                    #[cfg(not(build = "release"))]
+                    #[cfg(all(feature = "alloc", not(build = "release")))]
                     {
                         match (s.is_payloaded(), o.is_payloaded()) {
-                            (true, true) => unimplemented!("input: two nans"),
+                            (true, true) => return Dirty::from_raw(keep_first_error(s, o)),
                             (false, true) => return Dirty::from_raw(o),
                             (true, false) => return Dirty::from_raw(s),
                             (false, false) => {
                                 if result.is_nan() {
-                                    let errno = FLOAT_ERROR_BUFFER.insert(
-                                        FloatError::mul(self.as_raw(), other.as_raw()));
-                                    return Dirty::from_raw(NanPack::set_payload(errno))
+                                    return Dirty::from_raw(FLOAT_ERROR_BUFFER.insert(
+                                        FloatError::mul(self.as_raw(), other.as_raw())))
                                 }
                             },
                         }
+                        if range_warnings_enabled() {
+                            if let Some(w) = FloatError::range(RangeOp::Mul, s, o, result) {
+                                FLOAT_ERROR_BUFFER.warn(w);
+                            }
+                        }
                     }
                     Dirty::from_raw(result)
                 }
@@ -73,26 +127,62 @@ macro_rules! impl_common_traits {
             impl<B, F> Div<B> for $name
             where
                 F: Float + NanPack<usize>,
-                B: UncheckedConv<F> + Copy,
+                B: AsRaw<F> + Copy,
             {
                 type Output = Dirty<F>;
 
                 #[inline]
+                #[track_caller]
                 fn div(self, other: B) -> Self::Output {
                     let s = self.as_raw();
                     let o = other.as_raw();
                     let result = s / o;
-                    #[cfg(not(build = "release"))]
+                    #[cfg(all(feature = "alloc", not(build = "release")))]
+                    {
+                        match (s.is_payloaded(), o.is_payloaded()) {
+                            (true, true) => return Dirty::from_raw(keep_first_error(s, o)),
+                            (false, true) => return Dirty::from_raw(o),
+                            (true, false) => return Dirty::from_raw(s),
+                            (false, false) => {
+                                if result.is_nan() {
+                                    return Dirty::from_raw(FLOAT_ERROR_BUFFER.insert(
+                                        FloatError::div(self.as_raw(), other.as_raw())))
+                                }
+                            },
+                        }
+                        if range_warnings_enabled() {
+                            if let Some(w) = FloatError::range(RangeOp::Div, s, o, result) {
+                                FLOAT_ERROR_BUFFER.warn(w);
+                            }
+                        }
+                    }
+                    Dirty::from_raw(result)
+                }
+            }
+
+            impl<B, F> Rem<B> for $name
+            where
+                F: Float + NanPack<usize>,
+                B: AsRaw<F> + Copy,
+            {
+                type Output = Dirty<F>;
+
+                #[inline]
+                #[track_caller]
+                fn rem(self, other: B) -> Self::Output {
+                    let s = self.as_raw();
+                    let o = other.as_raw();
+                    let result = s % o;
+                    #[cfg(all(feature = "alloc", not(build = "release")))]
                     {
                         match (s.is_payloaded(), o.is_payloaded()) {
-                            (true, true) => unimplemented!("input: two nans"),
+                            (true, true) => return Dirty::from_raw(keep_first_error(s, o)),
                             (false, true) => return Dirty::from_raw(o),
                             (true, false) => return Dirty::from_raw(s),
                             (false, false) => {
                                 if result.is_nan() {
-                                    let errno = FLOAT_ERROR_BUFFER.insert(
-                                        FloatError::div(self.as_raw(), other.as_raw()));
-                                    return Dirty::from_raw(NanPack::set_payload(errno))
+                                    return Dirty::from_raw(FLOAT_ERROR_BUFFER.insert(
+                                        FloatError::rem(self.as_raw(), other.as_raw())))
                                 }
                             },
                         }
@@ -114,7 +204,7 @@ macro_rules! impl_common_traits {
 
             impl<B, F> PartialEq<B> for $name
             where
-                B: UncheckedConv<F> + Copy,
+                B: AsRaw<F> + Copy,
                 F: Float,
             {
                 #[inline]
@@ -125,7 +215,7 @@ macro_rules! impl_common_traits {
 
             impl<B, F> PartialOrd<B> for $name
             where
-                B: UncheckedConv<F> + Copy,
+                B: AsRaw<F> + Copy,
                 F: Float,
             {
                 #[inline]
@@ -174,13 +264,49 @@ impl Into<Dirty<f32>> for f32 {
 impl<F> Into<Dirty<F>> for Clean<F>
 where
     F: Float + NanPack<usize>,
-    Self: UncheckedConv<F>,
+    Self: AsRaw<F>,
 {
     fn into(self) -> Dirty<F> {
         Dirty::new(self.as_raw())
     }
 }
 
+// lossless conversions from integers
+macro_rules! impl_from_int {
+    ( $f: ty; $( $int: ty ),* ) => {
+        $(
+            impl From<$int> for Clean<$f> {
+                #[inline]
+                fn from(i: $int) -> Self {
+                    Clean::from_raw(<$f as From<$int>>::from(i))
+                }
+            }
+        )*
+    }
+}
+
+impl_from_int!(f64; i8, i16, i32, u8, u16, u32);
+impl_from_int!(f32; i8, i16, u8, u16);
+
+impl From<Clean<f32>> for Clean<f64> {
+    fn from(f: Clean<f32>) -> Self {
+        Clean::from_raw(f.as_raw() as f64)
+    }
+}
+
+impl From<Dirty<f32>> for Dirty<f64> {
+    fn from(f: Dirty<f32>) -> Self {
+        let f = f.as_raw();
+        #[cfg(all(feature = "alloc", not(build = "release")))]
+        {
+            if f.is_payloaded() {
+                return Dirty::from_raw(FLOAT_ERROR_BUFFER.remap(f));
+            }
+        }
+        Dirty::from_raw(f as f64)
+    }
+}
+
 
 impl<F> Eq for Clean<F>
 where
@@ -211,7 +337,7 @@ where
 impl<F> fmt::Display for Clean<F>
 where
     F: Float + fmt::Display,
-    Self: UncheckedConv<F>,
+    Self: AsRaw<F>,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", (*self).as_raw())
@@ -221,9 +347,263 @@ where
 impl<F> fmt::Display for Dirty<F>
 where
     F: Float + fmt::Display,
-    Self: UncheckedConv<F>,
+    Self: AsRaw<F>,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", (*self).as_raw())
     }
 }
+
+// num-traits integration. `Dirty` can represent every result of a `Float`, so it gets the whole
+// numeric tower. `Clean` only gets the traits that can't produce a NaN.
+
+impl<F> Zero for Dirty<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    fn zero() -> Self {
+        Dirty::from_raw(F::zero())
+    }
+
+    #[inline]
+    fn is_zero(&self) -> bool {
+        self.as_raw().is_zero()
+    }
+}
+
+impl<F> One for Dirty<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    fn one() -> Self {
+        Dirty::from_raw(F::one())
+    }
+}
+
+impl<F> Num for Dirty<F>
+where
+    F: Float + NanPack<usize>,
+{
+    type FromStrRadixErr = F::FromStrRadixErr;
+
+    fn from_str_radix(s: &str, radix: u32) -> Result<Self, Self::FromStrRadixErr> {
+        F::from_str_radix(s, radix).map(Dirty::from_raw)
+    }
+}
+
+impl<F> Signed for Dirty<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    fn abs(&self) -> Self {
+        Dirty::from_raw(self.as_raw().abs())
+    }
+
+    #[inline]
+    fn abs_sub(&self, other: &Self) -> Self {
+        Dirty::from_raw(Float::abs_sub(self.as_raw(), other.as_raw()))
+    }
+
+    #[inline]
+    fn signum(&self) -> Self {
+        Dirty::from_raw(self.as_raw().signum())
+    }
+
+    #[inline]
+    fn is_positive(&self) -> bool {
+        self.as_raw().is_sign_positive() && !self.as_raw().is_nan()
+    }
+
+    #[inline]
+    fn is_negative(&self) -> bool {
+        self.as_raw().is_sign_negative() && !self.as_raw().is_nan()
+    }
+}
+
+// delegates `Float` methods of `Dirty` to the underlying float
+macro_rules! delegate_float {
+    ( constructors: $( $constructor: ident ),* ; ) => {
+        $(
+            #[inline]
+            fn $constructor() -> Self {
+                Dirty::from_raw(F::$constructor())
+            }
+        )*
+    };
+    ( predicates: $( $predicate: ident ),* ; ) => {
+        $(
+            #[inline]
+            fn $predicate(self) -> bool {
+                Float::$predicate(self.as_raw())
+            }
+        )*
+    };
+    ( unary: $( $unary: ident ),* ; ) => {
+        $(
+            #[inline]
+            fn $unary(self) -> Self {
+                Dirty::from_raw(Float::$unary(self.as_raw()))
+            }
+        )*
+    };
+    ( binary: $( $binary: ident ),* ; ) => {
+        $(
+            #[inline]
+            fn $binary(self, other: Self) -> Self {
+                Dirty::from_raw(Float::$binary(self.as_raw(), other.as_raw()))
+            }
+        )*
+    };
+}
+
+impl<F> Float for Dirty<F>
+where
+    F: Float + NanPack<usize>,
+{
+    delegate_float!(constructors: nan, infinity, neg_infinity, neg_zero, min_value,
+                    min_positive_value, max_value, epsilon;);
+    delegate_float!(predicates: is_nan, is_infinite, is_finite, is_normal, is_sign_positive,
+                    is_sign_negative;);
+    delegate_float!(unary: floor, ceil, round, trunc, fract, abs, signum, recip, sqrt, exp, exp2,
+                    ln, log2, log10, cbrt, sin, cos, tan, asin, acos, atan, exp_m1, ln_1p, sinh,
+                    cosh, tanh, asinh, acosh, atanh, to_degrees, to_radians;);
+    delegate_float!(binary: powf, log, max, min, abs_sub, hypot, atan2, copysign;);
+
+    #[inline]
+    fn classify(self) -> FpCategory {
+        self.as_raw().classify()
+    }
+
+    #[inline]
+    fn mul_add(self, a: Self, b: Self) -> Self {
+        Dirty::from_raw(self.as_raw().mul_add(a.as_raw(), b.as_raw()))
+    }
+
+    #[inline]
+    fn powi(self, n: i32) -> Self {
+        Dirty::from_raw(self.as_raw().powi(n))
+    }
+
+    #[inline]
+    fn sin_cos(self) -> (Self, Self) {
+        let (s, c) = self.as_raw().sin_cos();
+        (Dirty::from_raw(s), Dirty::from_raw(c))
+    }
+
+    #[inline]
+    fn integer_decode(self) -> (u64, i16, i8) {
+        self.as_raw().integer_decode()
+    }
+}
+
+impl<F> Bounded for Clean<F>
+where
+    F: Float,
+{
+    #[inline]
+    fn min_value() -> Self {
+        Clean::from_raw(F::min_value())
+    }
+
+    #[inline]
+    fn max_value() -> Self {
+        Clean::from_raw(F::max_value())
+    }
+}
+
+impl<F> Bounded for Dirty<F>
+where
+    F: Float,
+{
+    #[inline]
+    fn min_value() -> Self {
+        Dirty::from_raw(F::min_value())
+    }
+
+    #[inline]
+    fn max_value() -> Self {
+        Dirty::from_raw(F::max_value())
+    }
+}
+
+macro_rules! impl_primitive_traits {
+    ( $( $name: ident ),* ) => {
+        $(
+            impl<F> ToPrimitive for $name<F>
+            where
+                F: Float,
+            {
+                #[inline]
+                fn to_i64(&self) -> Option<i64> {
+                    self.as_raw().to_i64()
+                }
+
+                #[inline]
+                fn to_u64(&self) -> Option<u64> {
+                    self.as_raw().to_u64()
+                }
+
+                #[inline]
+                fn to_f32(&self) -> Option<f32> {
+                    self.as_raw().to_f32()
+                }
+
+                #[inline]
+                fn to_f64(&self) -> Option<f64> {
+                    self.as_raw().to_f64()
+                }
+            }
+
+            // `Clean` rejects NaN here, `Dirty` accepts everything
+            impl<F> FromPrimitive for $name<F>
+            where
+                F: Float + NanPack<usize>,
+            {
+                #[inline]
+                fn from_i64(n: i64) -> Option<Self> {
+                    <Self as NumCast>::from(n)
+                }
+
+                #[inline]
+                fn from_u64(n: u64) -> Option<Self> {
+                    <Self as NumCast>::from(n)
+                }
+
+                #[inline]
+                fn from_f32(n: f32) -> Option<Self> {
+                    <Self as NumCast>::from(n)
+                }
+
+                #[inline]
+                fn from_f64(n: f64) -> Option<Self> {
+                    <Self as NumCast>::from(n)
+                }
+            }
+        )*
+    }
+}
+
+impl_primitive_traits!(Clean, Dirty);
+
+impl<F> NumCast for Clean<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    fn from<T: ToPrimitive>(n: T) -> Option<Self> {
+        <F as NumCast>::from(n).and_then(|f| Clean::try_new(f).ok())
+    }
+}
+
+impl<F> NumCast for Dirty<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    fn from<T: ToPrimitive>(n: T) -> Option<Self> {
+        <F as NumCast>::from(n).map(Dirty::from_raw)
+    }
+}
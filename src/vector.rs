@@ -0,0 +1,283 @@
+// Copyright 2017 Matthias Tellen
+//
+// Permission is hereby granted,  free of charge,  to any person  obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without  limitation  the  rights to use,  copy,  modify,  merge,  publish,  distribute,
+// sublicense,  and/or sell copies of the Software,  and to permit  persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The  above  copyright  notice and this permission notice shall be included  in all copies or sub-
+// stantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS",  WITHOUT WARRANTY OF ANY KIND,  EXPRESS OR IMPLIED,  INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,  FITNESS  FOR A PARTICULAR PURPOSE AND NON-
+// INFRINGEMENT.  IN NO EVENT SHALL THE AUTHORS  OR  COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAM-
+// AGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,  TORT OR OTHERWISE, ARISING FROM, OUT
+// OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Small vectors for geometry.
+//!
+//! `Vec2`, `Vec3` and `Vec4` have `Clean` components, and their arithmetic works component-wise
+//! with the scalar operators, so a NaN produced anywhere is tracked like a scalar one. The results
+//! are `DirtyVec2`, `DirtyVec3` and `DirtyVec4`, which have to be sanitized.
+
+use core::ops::{Add, Div, Mul, Neg, Sub};
+use num_traits::float::Float;
+use super::{AsRaw, Clean, CleanFloat, Dirty, UncheckedConv};
+use ::error::FloatError;
+use ::nanpack::NanPack;
+
+macro_rules! impl_vector {
+    ( $( $name: ident, $dirty: ident, [$first: ident $(, $c: ident)*]; )* ) => {
+        $(
+            #[derive(Debug, Copy, Clone, PartialEq)]
+            pub struct $name<F: Float> {
+                pub $first: Clean<F>,
+                $( pub $c: Clean<F>, )*
+            }
+
+            #[derive(Debug, Copy, Clone)]
+            pub struct $dirty<F: Float> {
+                pub $first: Dirty<F>,
+                $( pub $c: Dirty<F>, )*
+            }
+
+            impl<F> $name<F>
+            where
+                F: Float + NanPack<usize>,
+            {
+                #[inline]
+                pub fn new($first: Clean<F> $(, $c: Clean<F>)*) -> Self {
+                    $name { $first $(, $c)* }
+                }
+
+                #[inline]
+                pub fn taint(self) -> $dirty<F> {
+                    $dirty { $first: self.$first.taint() $(, $c: self.$c.taint())* }
+                }
+
+                #[inline]
+                pub fn dot(self, other: Self) -> Dirty<F> {
+                    self.$first * other.$first $( + self.$c * other.$c )*
+                }
+
+                /// The euclidean length, which overflows to infinity for huge components.
+                #[inline]
+                pub fn length(self) -> Dirty<F> {
+                    CleanFloat::sqrt(self.dot(self))
+                }
+
+                /// The vector scaled to length one. Fails for zero-length vectors and those with
+                /// infinite components, which have no direction.
+                #[inline]
+                pub fn normalize(self) -> Result<Self, FloatError> {
+                    // dividing by the largest component first keeps the squares from overflowing,
+                    // and detects the zero vector through the NaN of `0 / 0`
+                    let scale = Clean::from_raw(
+                        self.$first.as_raw().abs() $( .max(self.$c.as_raw().abs()) )*
+                    );
+                    let scaled = $dirty {
+                        $first: self.$first / scale,
+                        $( $c: self.$c / scale, )*
+                    }.sanitize()?;
+                    let length = scaled.length().as_raw();
+                    Ok($name {
+                        $first: scaled.$first.map(|x| x / length),
+                        $( $c: scaled.$c.map(|x| x / length), )*
+                    })
+                }
+            }
+
+            impl<F> $dirty<F>
+            where
+                F: Float + NanPack<usize>,
+            {
+                #[inline]
+                pub fn new($first: Dirty<F> $(, $c: Dirty<F>)*) -> Self {
+                    $dirty { $first $(, $c)* }
+                }
+
+                #[inline]
+                pub fn sanitize(self) -> Result<$name<F>, FloatError> {
+                    let parts = [self.$first $(, self.$c)*];
+                    let mut errors = parts.iter().filter_map(|x| x.sanitize().err());
+                    if let Some(err) = errors.next() {
+                        // sanitizing the remaining components removes their errors from the buffer
+                        errors.for_each(drop);
+                        return Err(err);
+                    }
+                    Ok($name {
+                        $first: Clean::from_raw(self.$first.as_raw()),
+                        $( $c: Clean::from_raw(self.$c.as_raw()), )*
+                    })
+                }
+            }
+
+            impl<F> From<$name<F>> for $dirty<F>
+            where
+                F: Float + NanPack<usize>,
+            {
+                #[inline]
+                fn from(v: $name<F>) -> Self {
+                    v.taint()
+                }
+            }
+
+            impl_vector_ops!($name, $name, $dirty, [$first $(, $c)*]);
+            impl_vector_ops!($name, $dirty, $dirty, [$first $(, $c)*]);
+            impl_vector_ops!($dirty, $name, $dirty, [$first $(, $c)*]);
+            impl_vector_ops!($dirty, $dirty, $dirty, [$first $(, $c)*]);
+            impl_vector_scale!($name, $dirty, [$first $(, $c)*]);
+            impl_vector_scale!($dirty, $dirty, [$first $(, $c)*]);
+        )*
+    }
+}
+
+macro_rules! impl_vector_ops {
+    ( $lhs: ident, $rhs: ident, $dirty: ident, [$( $c: ident ),*] ) => {
+        impl<F> Add<$rhs<F>> for $lhs<F>
+        where
+            F: Float + NanPack<usize>,
+        {
+            type Output = $dirty<F>;
+
+            #[inline]
+            fn add(self, other: $rhs<F>) -> Self::Output {
+                $dirty { $( $c: self.$c + other.$c ),* }
+            }
+        }
+
+        impl<F> Sub<$rhs<F>> for $lhs<F>
+        where
+            F: Float + NanPack<usize>,
+        {
+            type Output = $dirty<F>;
+
+            #[inline]
+            fn sub(self, other: $rhs<F>) -> Self::Output {
+                $dirty { $( $c: self.$c - other.$c ),* }
+            }
+        }
+    }
+}
+
+// scaling by anything with a raw value, and negation
+macro_rules! impl_vector_scale {
+    ( $name: ident, $dirty: ident, [$( $c: ident ),*] ) => {
+        impl<B, F> Mul<B> for $name<F>
+        where
+            F: Float + NanPack<usize>,
+            B: AsRaw<F> + Copy,
+        {
+            type Output = $dirty<F>;
+
+            #[inline]
+            fn mul(self, other: B) -> Self::Output {
+                $dirty { $( $c: self.$c * other ),* }
+            }
+        }
+
+        impl<B, F> Div<B> for $name<F>
+        where
+            F: Float + NanPack<usize>,
+            B: AsRaw<F> + Copy,
+        {
+            type Output = $dirty<F>;
+
+            #[inline]
+            fn div(self, other: B) -> Self::Output {
+                $dirty { $( $c: self.$c / other ),* }
+            }
+        }
+
+        impl<F> Neg for $name<F>
+        where
+            F: Float + NanPack<usize>,
+        {
+            type Output = Self;
+
+            #[inline]
+            fn neg(self) -> Self {
+                $name { $( $c: -self.$c ),* }
+            }
+        }
+    }
+}
+
+impl_vector! {
+    Vec2, DirtyVec2, [x, y];
+    Vec3, DirtyVec3, [x, y, z];
+    Vec4, DirtyVec4, [x, y, z, w];
+}
+
+impl<F> Vec3<F>
+where
+    F: Float + NanPack<usize>,
+{
+    #[inline]
+    pub fn cross(self, other: Self) -> DirtyVec3<F> {
+        DirtyVec3 {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use F64;
+
+    fn c(f: f64) -> Clean<f64> {
+        F64::try_new(f).unwrap()
+    }
+
+    fn v3(x: f64, y: f64, z: f64) -> Vec3<f64> {
+        Vec3::new(c(x), c(y), c(z))
+    }
+
+    #[test]
+    fn arithmetic() {
+        let (a, b) = (v3(1.0, 2.0, 3.0), v3(4.0, -5.0, 6.0));
+        assert_eq!((a + b).sanitize().unwrap(), v3(5.0, -3.0, 9.0));
+        assert_eq!((a - b).sanitize().unwrap(), v3(-3.0, 7.0, -3.0));
+        assert_eq!((a + b - a).sanitize().unwrap(), b);
+        assert_eq!((a * 2.0).sanitize().unwrap(), v3(2.0, 4.0, 6.0));
+        assert_eq!((a / c(2.0)).sanitize().unwrap(), v3(0.5, 1.0, 1.5));
+        assert_eq!(-a, v3(-1.0, -2.0, -3.0));
+        assert_eq!(a.dot(b), 12.0);
+        assert_eq!(a.cross(b).sanitize().unwrap(), v3(27.0, 6.0, -13.0));
+        assert_eq!(Vec2::new(c(3.0), c(4.0)).length(), 5.0);
+        assert_eq!(Vec4::new(c(1.0), c(1.0), c(1.0), c(1.0)).length(), 2.0);
+    }
+
+    #[test]
+    fn normalize() {
+        let n = Vec2::new(c(3.0), c(-4.0)).normalize().unwrap();
+        assert_eq!((n.x.as_raw(), n.y.as_raw()), (0.6, -0.8));
+        let max = ::std::f64::MAX;
+        let n = v3(max, max, 0.0).normalize().unwrap();
+        assert!((n.length().as_raw() - 1.0).abs() < 1e-15);
+        assert!(v3(0.0, 0.0, 0.0).normalize().is_err());
+        assert!(v3(::std::f64::INFINITY, 1.0, 0.0).normalize().is_err());
+    }
+
+    #[test]
+    #[cfg(all(feature = "alloc", not(build = "release")))]
+    fn nan_errors() {
+        let err = v3(0.0, 0.0, 0.0).normalize().unwrap_err();
+        assert!(format!("{}", err).ends_with("Division zero by zero resulted in NaN"));
+        let inf = ::std::f64::INFINITY;
+        let err = (v3(1.0, inf, 1.0) * 0.0).sanitize().unwrap_err();
+        assert!(format!("{}", err).ends_with("Multiplication infinity by zero resulted in NaN"));
+
+        // only the first error is returned, the others are released
+        let v = v3(inf, 1.0, inf) * 0.0;
+        let copy = v.z;
+        let err = v.sanitize().unwrap_err();
+        assert!(format!("{}", err).ends_with("Multiplication infinity by zero resulted in NaN"));
+        let err = copy.sanitize().unwrap_err();
+        assert!(format!("{}", err).ends_with("already retrieved or overwritten"));
+    }
+}